@@ -0,0 +1,40 @@
+use crate::natives::{misc::get_game_timer, network::get_network_time};
+use cfx_core::runtime::{sleep_until_clock, Clock, ClockSleep};
+use std::time::Duration;
+
+/// `GetGameTimer`: time since the game has started.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GameTimer;
+
+impl Clock for GameTimer {
+    fn now(&self) -> Duration {
+        Duration::from_millis(get_game_timer().max(0) as u64)
+    }
+}
+
+/// `GetNetworkTime`: time shared by all players of the session.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NetworkTime;
+
+impl Clock for NetworkTime {
+    fn now(&self) -> Duration {
+        Duration::from_millis(get_network_time().max(0) as u64)
+    }
+}
+
+/// Stops execution until `GetGameTimer` reaches `time`.
+pub fn sleep_until_game_time(time: Duration) -> ClockSleep<GameTimer> {
+    sleep_until_clock(GameTimer, time)
+}
+
+/// Stops execution until `GetNetworkTime` reaches `time`.
+///
+/// # Example
+/// ```rust,ignore
+/// // the server has sent the start time to every client
+/// cfx::client::clock::sleep_until_network_time(race.start_time).await;
+/// start_race();
+/// ```
+pub fn sleep_until_network_time(time: Duration) -> ClockSleep<NetworkTime> {
+    sleep_until_clock(NetworkTime, time)
+}
@@ -1,13 +1,8 @@
+pub mod clock;
 pub mod events;
 pub mod natives;
 pub mod task;
 
 pub fn emit_net<T: serde::Serialize>(event_name: &str, payload: T) {
-    if let Ok(payload) = rmp_serde::to_vec(&payload) {
-        natives::cfx::trigger_server_event_internal(
-            event_name,
-            payload.as_slice(),
-            payload.len() as _,
-        );
-    }
+    cfx_core::events::emit_to_server(event_name, payload);
 }
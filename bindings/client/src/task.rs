@@ -1,6 +1,5 @@
 use crate::natives::{player::*, streaming::*, task::*};
-use cfx_core::runtime::sleep_for;
-use std::time::Duration;
+use cfx_core::runtime::next_tick;
 
 #[derive(Debug)]
 pub struct TaskSequenceBuilder {
@@ -42,7 +41,7 @@ impl TaskSequenceBuilder {
         request_anim_dict(anim_dict);
 
         while !has_anim_dict_loaded(anim_dict) {
-            sleep_for(Duration::from_millis(5)).await;
+            next_tick().await;
         }
 
         task_play_anim(
@@ -82,12 +81,13 @@ impl TaskSequenceBuilder {
     pub async fn run_and_wait(self, clear_tasks: bool) {
         self.run(clear_tasks);
 
-        sleep_for(Duration::from_millis(10)).await;
+        // the sequence starts at the next frame
+        next_tick().await;
 
         let ped = player_ped_id();
 
         while crate::natives::task::get_sequence_progress(ped) != -1 {
-            sleep_for(Duration::from_millis(10)).await;
+            next_tick().await;
         }
     }
 }
@@ -0,0 +1,398 @@
+//! An application builder.
+//!
+//! [`App`] registers event handlers, exports, commands and tasks that share one state.
+//! Event handlers are `async` functions taking extractors: [`Source`], [`State`] and, as the last
+//! argument, [`Payload`]. They are [`crate::events::Handler`]s set with [`set_event_handler_with`].
+//!
+//! # Example
+//! ```rust,ignore
+//! use cfx::app::{App, Command, Payload, Source, State};
+//! use cfx::events::EventScope;
+//!
+//! #[derive(Default)]
+//! struct Bank {
+//!     balances: RefCell<HashMap<String, u32>>,
+//! }
+//!
+//! async fn give_money(Source(source): Source, State(bank): State<Bank>, Payload((amount,)): Payload<(u32,)>) {
+//!     *bank.balances.borrow_mut().entry(source).or_default() += amount;
+//! }
+//!
+//! async fn reset(State(bank): State<Bank>, _: Command) {
+//!     bank.balances.borrow_mut().clear();
+//! }
+//!
+//! async fn autosave(State(bank): State<Bank>) -> Result<(), SaveError> {
+//!     loop {
+//!         cfx::runtime::sleep_for(Duration::from_secs(60)).await;
+//!         save(&bank.balances.borrow())?;
+//!     }
+//! }
+//!
+//! App::with_state(Bank::default())
+//!     .event("bank:giveMoney", EventScope::Network, give_money)
+//!     .export("balance", |State(bank): State<Bank>, (player,): (String,)| {
+//!         bank.balances.borrow().get(&player).copied().unwrap_or(0)
+//!     })
+//!     .command("resetbank", true, reset)
+//!     .task("autosave", autosave)
+//!     .run();
+//! ```
+use crate::{
+    events::{set_event_handler_with, EventScope, EventSource, Handler},
+    invoker::Val,
+    ref_funcs::RefFunction,
+};
+
+use futures::{future::Map, Future, FutureExt};
+use serde::{de::DeserializeOwned, de::IgnoredAny, Serialize};
+use std::{convert::Infallible, fmt::Display, marker::PhantomData, ops::Deref, rc::Rc};
+
+/// Extracts a source of an event as a string. Use [`EventSource`] to get a typed one.
+#[derive(Debug, Clone)]
+pub struct Source(pub String);
+
+/// Extracts a payload of an event decoding it from messagepack.
+///
+/// It has to be the last argument of a handler.
+#[derive(Debug, Clone)]
+pub struct Payload<T>(pub T);
+
+/// Extracts the shared state of [`App`].
+#[derive(Debug)]
+pub struct State<S>(pub Rc<S>);
+
+impl<S> Clone for State<S> {
+    fn clone(&self) -> Self {
+        State(self.0.clone())
+    }
+}
+
+impl<S> Deref for State<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.0
+    }
+}
+
+/// Types that can be extracted from an event without its payload.
+pub trait FromEventParts<S>: Sized {
+    fn from_event_parts(source: &EventSource, state: &Rc<S>) -> Self;
+}
+
+impl<S> FromEventParts<S> for Source {
+    fn from_event_parts(source: &EventSource, _: &Rc<S>) -> Self {
+        Source(source.to_string())
+    }
+}
+
+impl<S> FromEventParts<S> for EventSource {
+    fn from_event_parts(source: &EventSource, _: &Rc<S>) -> Self {
+        source.clone()
+    }
+}
+
+impl<S> FromEventParts<S> for State<S> {
+    fn from_event_parts(_: &EventSource, state: &Rc<S>) -> Self {
+        State(state.clone())
+    }
+}
+
+mod private {
+    pub enum ViaParts {}
+    pub enum ViaPayload {}
+}
+
+/// Types that can be the last argument of a handler.
+///
+/// The payload is decoded by [`set_event_handler_with`] as [`FromEvent::Payload`].
+/// Every [`FromEventParts`] type is also [`FromEvent`] and ignores the payload.
+pub trait FromEvent<S, M = private::ViaPayload>: Sized {
+    type Payload: DeserializeOwned + 'static;
+
+    fn from_event(source: EventSource, payload: Self::Payload, state: &Rc<S>) -> Self;
+}
+
+impl<S, T: FromEventParts<S>> FromEvent<S, private::ViaParts> for T {
+    type Payload = IgnoredAny;
+
+    fn from_event(source: EventSource, _: IgnoredAny, state: &Rc<S>) -> Self {
+        T::from_event_parts(&source, state)
+    }
+}
+
+impl<S, T: DeserializeOwned + 'static> FromEvent<S> for Payload<T> {
+    type Payload = T;
+
+    fn from_event(_: EventSource, payload: T, _: &Rc<S>) -> Self {
+        Payload(payload)
+    }
+}
+
+/// Output of handlers and tasks. Errors are logged.
+pub trait HandlerOutput {
+    type Error: Display;
+
+    fn into_result(self) -> Result<(), Self::Error>;
+
+    /// Logs an error if there is one.
+    fn report(self, context: &str)
+    where
+        Self: Sized,
+    {
+        if let Err(err) = self.into_result() {
+            crate::log(format!("{} has failed: {}", context, err));
+        }
+    }
+}
+
+impl HandlerOutput for () {
+    type Error = Infallible;
+
+    fn into_result(self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl<T, E: Display> HandlerOutput for Result<T, E> {
+    type Error = E;
+
+    fn into_result(self) -> Result<(), E> {
+        self.map(drop)
+    }
+}
+
+/// An `async` function that takes up to 4 arguments: [`FromEventParts`] ones
+/// followed by a [`FromEvent`] one, usually [`Payload`].
+pub trait AppHandler<S, Args>: 'static {
+    type Payload: DeserializeOwned + 'static;
+    type Future: Future + 'static;
+
+    /// Extracts the arguments and calls the function.
+    fn call(&self, source: EventSource, payload: Self::Payload, state: &Rc<S>) -> Self::Future;
+}
+
+impl<S, Func, Fut> AppHandler<S, ()> for Func
+where
+    Func: Fn() -> Fut + 'static,
+    Fut: Future + 'static,
+{
+    type Payload = IgnoredAny;
+    type Future = Fut;
+
+    fn call(&self, _: EventSource, _: IgnoredAny, _: &Rc<S>) -> Fut {
+        (self)()
+    }
+}
+
+macro_rules! impl_handler {
+    ([$($part:ident),*] $last:ident) => {
+        impl<S, Func, Fut, M, $($part,)* $last> AppHandler<S, (M, $($part,)* $last,)> for Func
+        where
+            Func: Fn($($part,)* $last) -> Fut + 'static,
+            Fut: Future + 'static,
+            $($part: FromEventParts<S>,)*
+            $last: FromEvent<S, M>,
+        {
+            type Payload = $last::Payload;
+            type Future = Fut;
+
+            #[allow(non_snake_case)]
+            fn call(&self, source: EventSource, payload: Self::Payload, state: &Rc<S>) -> Fut {
+                $(let $part = $part::from_event_parts(&source, state);)*
+                let $last = $last::from_event(source, payload, state);
+                (self)($($part,)* $last)
+            }
+        }
+    };
+}
+
+impl_handler!([] A1);
+impl_handler!([A1] A2);
+impl_handler!([A1, A2] A3);
+impl_handler!([A1, A2, A3] A4);
+
+type Output<H, S, Args> = <<H as AppHandler<S, Args>>::Future as Future>::Output;
+type OutputError<H, S, Args> = <Output<H, S, Args> as HandlerOutput>::Error;
+
+/// [`Handler`] that passes the app state to an [`AppHandler`].
+struct WithState<S, H, Args> {
+    handler: H,
+    state: Rc<S>,
+    _args: PhantomData<fn() -> Args>,
+}
+
+impl<S, H, Args> Handler<H::Payload> for WithState<S, H, Args>
+where
+    H: AppHandler<S, Args>,
+    Output<H, S, Args>: HandlerOutput,
+{
+    type Response = ();
+    type Error = OutputError<H, S, Args>;
+    type Future = Map<H::Future, fn(Output<H, S, Args>) -> Result<(), Self::Error>>;
+
+    fn handle(&mut self, source: EventSource, payload: H::Payload) -> Self::Future {
+        self.handler
+            .call(source, payload, &self.state)
+            .map(HandlerOutput::into_result)
+    }
+}
+
+/// A command invocation passed to [`App::command`] handlers.
+#[derive(Debug, Clone)]
+pub struct Command {
+    /// Server id of a player who has called the command, `0` for the server console.
+    pub source: i32,
+    pub args: Vec<String>,
+    /// The whole command line.
+    pub raw: String,
+}
+
+type Setup<S> = Box<dyn FnOnce(&Rc<S>)>;
+
+/// A builder of a resource. Nothing is registered until [`App::run`] is called.
+pub struct App<S> {
+    state: Rc<S>,
+    setup: Vec<Setup<S>>,
+}
+
+impl App<()> {
+    /// Creates an app without state.
+    pub fn new() -> App<()> {
+        App::with_state(())
+    }
+}
+
+impl Default for App<()> {
+    fn default() -> Self {
+        App::new()
+    }
+}
+
+impl<S: 'static> App<S> {
+    /// Creates an app with the shared state available with the [`State`] extractor.
+    pub fn with_state(state: S) -> App<S> {
+        App {
+            state: Rc::new(state),
+            setup: Vec::new(),
+        }
+    }
+
+    /// Sets an event handler. Every event spawns a new task.
+    ///
+    /// Events with a payload that can't be decoded are dropped and passed to the hook
+    /// set by [`crate::events::set_decode_error_hook`].
+    pub fn event<H, Args>(mut self, event_name: &str, scope: EventScope, handler: H) -> Self
+    where
+        H: AppHandler<S, Args>,
+        Args: 'static,
+        Output<H, S, Args>: HandlerOutput,
+    {
+        let event_name = event_name.to_owned();
+
+        self.setup.push(Box::new(move |state| {
+            let handler = WithState {
+                handler,
+                state: state.clone(),
+                _args: PhantomData,
+            };
+
+            let context = format!("event handler {:?}", event_name);
+            let on_result = move |result: Result<(), OutputError<H, S, Args>>| {
+                result.report(&context);
+            };
+
+            // handlers live as long as the resource
+            set_event_handler_with(&event_name, handler, scope, on_result).detach();
+        }));
+
+        self
+    }
+
+    /// Makes an export. See [`crate::exports::make_export`].
+    ///
+    /// Exports are called synchronously so the function isn't `async`.
+    pub fn export<F, In, Out>(mut self, export_name: &str, func: F) -> Self
+    where
+        F: Fn(State<S>, In) -> Out + 'static,
+        In: DeserializeOwned,
+        Out: Serialize,
+    {
+        let export_name = export_name.to_owned();
+
+        self.setup.push(Box::new(move |state| {
+            let state = state.clone();
+            let func = RefFunction::new(move |input: In| func(State(state.clone()), input));
+
+            crate::exports::make_export(&export_name, func).detach();
+        }));
+
+        self
+    }
+
+    /// Registers a command with `RegisterCommand`. Every call spawns a new task.
+    pub fn command<F, Fut>(mut self, command_name: &str, restricted: bool, handler: F) -> Self
+    where
+        F: Fn(State<S>, Command) -> Fut + 'static,
+        Fut: Future + 'static,
+        Fut::Output: HandlerOutput,
+    {
+        let command_name = command_name.to_owned();
+
+        self.setup.push(Box::new(move |state| {
+            let state = state.clone();
+            let context = format!("command {:?}", command_name);
+
+            let func = RefFunction::new(move |(source, args, raw): (i32, Vec<String>, String)| {
+                let command = Command { source, args, raw };
+                let future = handler(State(state.clone()), command);
+                let context = context.clone();
+
+                let _ = crate::runtime::spawn(async move {
+                    future.await.report(&context);
+                });
+            });
+
+            let args = &[
+                Val::String(&command_name),
+                Val::RefFunc(func),
+                Val::Bool(restricted),
+            ];
+
+            let _ = crate::invoker::invoke::<(), _>(0x5FA79B0F, args); // REGISTER_COMMAND
+        }));
+
+        self
+    }
+
+    /// Spawns a named task when the app starts.
+    pub fn task<F, Fut>(mut self, task_name: &str, task: F) -> Self
+    where
+        F: FnOnce(State<S>) -> Fut + 'static,
+        Fut: Future + 'static,
+        Fut::Output: HandlerOutput,
+    {
+        let task_name = task_name.to_owned();
+
+        self.setup.push(Box::new(move |state| {
+            let future = task(State(state.clone()));
+            let context = format!("task {:?}", task_name);
+
+            let _ = crate::runtime::spawn_named(&task_name, async move {
+                future.await.report(&context);
+            });
+        }));
+
+        self
+    }
+
+    /// Registers everything in order of the builder calls.
+    pub fn run(self) {
+        let App { state, setup } = self;
+
+        for setup in setup {
+            setup(&state);
+        }
+    }
+}
@@ -3,32 +3,171 @@
 //! Currently the best method to use [`subscribe`] (allows you to use it with async/await syntax).
 //!
 //! Or with [`set_event_handler_closure`]
+//!
+//! Events can be declared next to their payload with [`CfxEvent`].
+//!
+//! An event can have any number of subscribers. Handlers are kept until
+//! the returned [`Subscription`] is dropped.
 use futures::{channel::mpsc::unbounded, Future, Stream, StreamExt};
 
+/// Generates `emit`, `subscribe` and `set_handler` for a payload type. Network events also get
+/// `emit_to_server`, `emit_to_client` and `emit_to_all_clients`.
+///
+/// # Example
+/// ```rust,ignore
+/// use cfx::events::CfxEvent;
+///
+/// #[derive(Serialize, Deserialize, CfxEvent)]
+/// #[cfx(name = "bank:deposit", scope = "network")]
+/// struct Deposit {
+///     amount: u32,
+/// }
+///
+/// // client
+/// Deposit { amount: 100 }.emit_to_server();
+///
+/// // server
+/// let deposits = Deposit::subscribe();
+/// ```
+///
+/// Use `#[cfx(crate = "cfx_core")]` if the `cfx` crate is renamed or not used directly.
+pub use cfx_macros::CfxEvent;
+
 use crate::invoker::Val;
 use crate::wasm_impl::events::*;
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::{borrow::Cow, cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    marker::PhantomData,
+    rc::Rc,
+    str::FromStr,
+};
+
+/// A server id of a player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PlayerId(pub u32);
+
+impl fmt::Display for PlayerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Who has triggered an event.
+///
+/// Parsed from the source string of CitizenFX:
+/// * `""` is [`EventSource::Local`],
+/// * `net:65535` is [`EventSource::Server`] (on a client),
+/// * `net:<id>` is [`EventSource::Client`] (on a server),
+/// * `internal-net:<id>` is [`EventSource::InternalNet`], like a player who is still connecting.
+///
+/// Events with a source in an unknown format are logged and delivered as [`EventSource::Local`].
+///
+/// [`Display`](fmt::Display) formats the source the way natives expect a player:
+/// the id for network sources and an empty string for [`EventSource::Local`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EventSource {
+    /// Triggered by a script on the same side.
+    Local,
+    /// Triggered by a client.
+    Client(PlayerId),
+    /// Triggered by the server.
+    Server,
+    /// Triggered by the server internals.
+    InternalNet(String),
+}
+
+impl EventSource {
+    /// Id that the server uses as a source of client events.
+    pub const SERVER_ID: u32 = 65535;
+
+    /// Returns `true` for local events.
+    pub fn is_local(&self) -> bool {
+        matches!(self, EventSource::Local)
+    }
+
+    /// Returns `true` for events that came over the network: [`EventSource::Client`] and [`EventSource::Server`].
+    pub fn is_net(&self) -> bool {
+        matches!(self, EventSource::Client(_) | EventSource::Server)
+    }
+
+    /// Returns the player who has triggered an event.
+    pub fn player(&self) -> Option<PlayerId> {
+        match self {
+            EventSource::Client(player) => Some(*player),
+            _ => None,
+        }
+    }
+}
+
+/// An error returned when a source string has an unknown format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSourceError(String);
+
+impl fmt::Display for ParseSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown event source {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseSourceError {}
+
+impl FromStr for EventSource {
+    type Err = ParseSourceError;
+
+    fn from_str(source: &str) -> Result<EventSource, ParseSourceError> {
+        if source.is_empty() {
+            return Ok(EventSource::Local);
+        }
+
+        if let Some(id) = source.strip_prefix("net:") {
+            return match id.parse() {
+                Ok(EventSource::SERVER_ID) => Ok(EventSource::Server),
+                Ok(id) => Ok(EventSource::Client(PlayerId(id))),
+                Err(_) => Err(ParseSourceError(source.to_owned())),
+            };
+        }
+
+        if let Some(id) = source.strip_prefix("internal-net:") {
+            return Ok(EventSource::InternalNet(id.to_owned()));
+        }
+
+        Err(ParseSourceError(source.to_owned()))
+    }
+}
+
+impl fmt::Display for EventSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventSource::Local => Ok(()),
+            EventSource::Client(player) => player.fmt(f),
+            EventSource::Server => EventSource::SERVER_ID.fmt(f),
+            EventSource::InternalNet(id) => f.write_str(id),
+        }
+    }
+}
 
 /// A raw event contains bytes from the emitters.
 #[derive(Debug)]
 pub struct RawEvent {
     /// A source who triggered an event
-    pub source: String,
+    pub source: EventSource,
     /// Payload of an event
     pub payload: Vec<u8>,
 }
 
 pub struct RawEventRef<'a> {
-    pub source: Cow<'a, str>,
+    pub source: EventSource,
     pub payload: &'a [u8],
 }
 
 impl<'a> RawEventRef<'a> {
     pub(crate) fn to_raw_event(&self) -> RawEvent {
         RawEvent {
-            source: self.source.to_string(),
+            source: self.source.clone(),
             payload: self.payload.into(),
         }
     }
@@ -36,13 +175,14 @@ impl<'a> RawEventRef<'a> {
 
 /// An incoming event from CitizenFX.
 pub struct Event<'de, T: Deserialize<'de>> {
-    source: Cow<'de, str>,
+    source: EventSource,
     payload: T,
+    _marker: PhantomData<&'de ()>,
 }
 
 impl<'de, T: Deserialize<'de>> Event<'de, T> {
     /// Get a source that triggered that event.
-    pub fn source(&self) -> &str {
+    pub fn source(&self) -> &EventSource {
         &self.source
     }
 
@@ -57,9 +197,82 @@ impl<'de, T: Deserialize<'de>> Event<'de, T> {
     }
 }
 
+/// A payload of an event that couldn't be decoded.
+#[derive(Debug)]
+pub struct DecodeError {
+    /// Name of the event.
+    pub event_name: String,
+    /// A source who triggered the event.
+    pub source: EventSource,
+    /// Path to the value that failed, like `items[2].amount`. `.` is the whole payload.
+    pub path: String,
+    /// Size of the payload in bytes.
+    pub payload_size: usize,
+    /// The error returned by the messagepack decoder.
+    pub error: rmp_serde::decode::Error,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "event {:?} from {:?}: couldn't decode {} bytes at {}: {}",
+            self.event_name, self.source, self.payload_size, self.path, self.error
+        )
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// Decodes a payload and records the path of a failed value.
+pub(crate) fn decode_payload<T: DeserializeOwned>(
+    event_name: &str,
+    source: &EventSource,
+    payload: &[u8],
+) -> Result<T, DecodeError> {
+    let mut deserializer = rmp_serde::Deserializer::new(payload);
+
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|err| DecodeError {
+        event_name: event_name.to_owned(),
+        source: source.clone(),
+        path: err.path().to_string(),
+        payload_size: payload.len(),
+        error: err.into_inner(),
+    })
+}
+
+/// Sets a hook that is called for every event dropped by [`subscribe`], [`set_event_handler`],
+/// [`set_event_handler_closure`] and [`crate::app::App::event`] handlers because its payload
+/// couldn't be decoded.
+///
+/// By default these errors are logged with [`crate::log`].
+/// Errors returned by [`subscribe_fallible`] are not passed to the hook.
+///
+/// # Example
+/// ```rust,ignore
+/// cfx::events::set_decode_error_hook(|err| {
+///     cfx::log(format!("^1{} ({} bytes at {})", err.event_name, err.payload_size, err.path));
+/// });
+/// ```
+pub fn set_decode_error_hook<F>(hook: F)
+where
+    F: Fn(&DecodeError) + 'static,
+{
+    DECODE_ERROR_HOOK.with(|current| *current.borrow_mut() = Some(Rc::new(hook)));
+}
+
+/// Restores the default decode error hook that logs errors.
+pub fn reset_decode_error_hook() {
+    DECODE_ERROR_HOOK.with(|current| current.borrow_mut().take());
+}
+
 /// Unused for now
 pub struct EventOwned<T: DeserializeOwned> {
-    source: String,
+    source: EventSource,
     payload: T,
 }
 
@@ -96,43 +309,119 @@ pub enum EventScope {
 /// # Ok(())
 /// # }
 ///
-pub fn subscribe<'a, In>(event_name: &'a str, scope: EventScope) -> impl Stream<Item = Event<In>>
+pub fn subscribe<'a, In>(
+    event_name: &'a str,
+    scope: EventScope,
+) -> impl Stream<Item = Event<'a, In>>
+where
+    for<'de> In: Deserialize<'de> + 'a,
+{
+    let events = subscribe_fallible(event_name, scope);
+
+    events.filter_map(|event| async move { event.map_err(|err| report_decode_error(&err)).ok() })
+}
+
+/// Same as [`subscribe`] but yields events that couldn't be decoded as [`DecodeError`]
+/// instead of dropping them.
+///
+/// # Example
+/// ```rust,ignore
+/// let events = cfx::events::subscribe_fallible::<GiveMoney>("myCustomEvent", EventScope::Network);
+///
+/// while let Some(event) = events.next().await {
+///     match event {
+///         Ok(event) => give_money(event.into_inner()),
+///         Err(err) => cfx::log(format!("{:?} sent a bad payload: {}", err.source, err)),
+///     }
+/// }
+/// ```
+pub fn subscribe_fallible<'a, In>(
+    event_name: &'a str,
+    scope: EventScope,
+) -> impl Stream<Item = Result<Event<'a, In>, DecodeError>>
 where
     for<'de> In: Deserialize<'de> + 'a,
 {
     let mut events = subscribe_raw(event_name, scope);
+    let event_name = event_name.to_owned();
 
     async_stream::stream! {
         while let Some(event) = events.next().await {
-            if let Ok(payload) = rmp_serde::from_read_ref(&event.payload) {
-                let event = Event {
-                    source: Cow::from(event.source),
-                    payload,
-                };
-
-                yield event;
-            }
+            let payload = decode_payload(&event_name, &event.source, &event.payload);
+
+            yield payload.map(|payload| Event {
+                source: event.source,
+                payload,
+                _marker: PhantomData,
+            });
         }
     }
 }
 
 /// Same as [`subscribe`] but returns [`RawEvent`].
+///
+/// The subscription is removed when the stream is dropped.
 pub fn subscribe_raw(event_name: &str, scope: EventScope) -> impl Stream<Item = RawEvent> {
     let (tx, rx) = unbounded();
+    let queued = Rc::new(Cell::new(0));
 
-    EVENTS.with(|events| {
-        let sub = EventSub {
-            scope,
-            handler: EventHandler::Future(tx),
-        };
+    let subscription = add_sub(event_name, scope, EventHandler::Future(tx), queued.clone());
 
-        let mut events = events.borrow_mut();
-        events.insert(event_name.to_owned(), sub);
-    });
+    rx.inspect(move |_| {
+        // the stream owns the subscription
+        let _ = &subscription;
+        queued.set(queued.get().saturating_sub(1));
+    })
+}
 
-    let _ = crate::invoker::register_resource_as_event_handler(event_name);
+/// A registered event handler. The handler is removed when the guard is dropped.
+///
+/// Any number of handlers can listen to the same event.
+///
+/// # Example
+/// ```rust,ignore
+/// let subscription = set_event_handler_closure("playerDropped", on_player_dropped, EventScope::Local);
+///
+/// // keep the handler for the whole life of the resource
+/// subscription.detach();
+/// ```
+#[derive(Debug)]
+#[must_use = "the handler is removed when the subscription is dropped"]
+pub struct Subscription {
+    event_name: String,
+    id: u64,
+    active: bool,
+}
 
-    rx
+impl Subscription {
+    pub(crate) fn new(event_name: String, id: u64) -> Subscription {
+        Subscription {
+            event_name,
+            id,
+            active: true,
+        }
+    }
+
+    /// Name of the event.
+    pub fn event_name(&self) -> &str {
+        &self.event_name
+    }
+
+    /// Removes the handler.
+    pub fn unsubscribe(self) {}
+
+    /// Keeps the handler until the resource stops.
+    pub fn detach(mut self) {
+        self.active = false;
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if self.active {
+            remove_sub(&self.event_name, self.id);
+        }
+    }
 }
 
 /// Sets an event handler.
@@ -142,41 +431,82 @@ pub fn subscribe_raw(event_name: &str, scope: EventScope) -> impl Stream<Item =
 ///
 /// It is useful for events that contains [`crate::ref_funcs::ExternRefFunction`] to call it.
 /// Internaly this function is used in [`crate::exports::make_export`].
-pub fn set_event_handler_closure<In, Handler>(event_name: &str, handler: Handler, scope: EventScope)
+pub fn set_event_handler_closure<In, Handler>(
+    event_name: &str,
+    handler: Handler,
+    scope: EventScope,
+) -> Subscription
 where
     Handler: Fn(Event<In>) + 'static,
     In: DeserializeOwned,
 {
+    let name = event_name.to_owned();
+
     let raw_handler = move |raw_event: RawEventRef| {
         let RawEventRef {
             source, payload, ..
         } = raw_event;
 
-        let event = rmp_serde::from_read_ref::<_, In>(&payload).ok();
-
-        if let Some(payload) = event {
-            let event = Event { source, payload };
-
-            handler(event);
+        match decode_payload::<In>(&name, &source, payload) {
+            Ok(payload) => handler(Event {
+                source,
+                payload,
+                _marker: PhantomData,
+            }),
+            Err(err) => report_decode_error(&err),
         }
     };
 
-    EVENTS.with(|events| {
-        let sub = EventSub {
-            scope,
-            handler: EventHandler::Function(Box::new(raw_handler)),
-        };
+    set_event_handler_raw(event_name, raw_handler, scope)
+}
 
-        let mut events = events.borrow_mut();
-        events.insert(event_name.to_owned(), sub);
-    });
+/// Same as [`set_event_handler_closure`] but passes [`RawEventRef`].
+pub fn set_event_handler_raw<Handler>(
+    event_name: &str,
+    handler: Handler,
+    scope: EventScope,
+) -> Subscription
+where
+    Handler: Fn(RawEventRef) + 'static,
+{
+    add_sub(
+        event_name,
+        scope,
+        EventHandler::Function(Rc::new(handler)),
+        Rc::default(),
+    )
+}
 
-    let _ = crate::invoker::register_resource_as_event_handler(event_name);
+/// When the executor runs after an event has been received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DispatchMode {
+    /// Run the executor after every event. Streams from [`subscribe`] see an event
+    /// right after it has been triggered.
+    #[default]
+    Immediate,
+    /// Queue events and run the executor once at the next tick
+    /// or after `max_batch` events, whichever comes first.
+    ///
+    /// Closure handlers are still called immediately.
+    Coalesced { max_batch: u32 },
+}
+
+/// Sets how events are dispatched to tasks. Default is [`DispatchMode::Immediate`].
+///
+/// Coalescing helps when a burst of network events arrives in one frame:
+/// tasks are woken once for the whole burst instead of once per event.
+///
+/// # Example
+/// ```rust,ignore
+/// cfx::events::set_dispatch_mode(DispatchMode::Coalesced { max_batch: 128 });
+/// ```
+pub fn set_dispatch_mode(mode: DispatchMode) {
+    DISPATCH_MODE.with(|current| current.set(mode));
 }
 
 /// Emits a local event.
 pub fn emit<T: Serialize>(event_name: &str, payload: T) {
-    if let Ok(payload) = rmp_serde::to_vec_named(&payload) {
+    if let Some(payload) = encode_payload(&payload) {
         let args = &[
             Val::String(event_name),
             Val::Bytes(&payload),
@@ -187,54 +517,136 @@ pub fn emit<T: Serialize>(event_name: &str, payload: T) {
     }
 }
 
+/// Emits a network event to the server. Client only.
+pub fn emit_to_server<T: Serialize>(event_name: &str, payload: T) {
+    if let Some(payload) = encode_payload(&payload) {
+        let args = &[
+            Val::String(event_name),
+            Val::Bytes(&payload),
+            Val::Integer(payload.len() as _),
+        ];
+
+        let _ = crate::invoker::invoke::<(), _>(0x7FDD1128, args); // TRIGGER_SERVER_EVENT_INTERNAL
+    }
+}
+
+/// Emits a network event to a client. Server only.
+///
+/// Does nothing if the target isn't [`EventSource::Client`].
+pub fn emit_to_client<T: Serialize>(event_name: &str, target: &EventSource, payload: T) {
+    if let Some(player) = target.player() {
+        emit_to_player(event_name, &player.to_string(), payload);
+    }
+}
+
+/// Emits a network event to every client. Server only.
+pub fn emit_to_all_clients<T: Serialize>(event_name: &str, payload: T) {
+    emit_to_player(event_name, "-1", payload);
+}
+
+fn emit_to_player<T: Serialize>(event_name: &str, player: &str, payload: T) {
+    if let Some(payload) = encode_payload(&payload) {
+        let args = &[
+            Val::String(event_name),
+            Val::String(player),
+            Val::Bytes(&payload),
+            Val::Integer(payload.len() as _),
+        ];
+
+        let _ = crate::invoker::invoke::<(), _>(0x2F7A49E6, args); // TRIGGER_CLIENT_EVENT_INTERNAL
+    }
+}
+
+/// Local and network events are encoded the same way, structs as maps.
+fn encode_payload<T: Serialize>(payload: &T) -> Option<Vec<u8>> {
+    rmp_serde::to_vec_named(payload).ok()
+}
+
 pub trait Handler<Input: DeserializeOwned> {
     type Response;
     type Error;
     type Future: Future<Output = Result<Self::Response, Self::Error>>;
 
-    fn handle(&mut self, source: String, event: Input) -> Self::Future;
+    fn handle(&mut self, source: EventSource, event: Input) -> Self::Future;
+}
+
+/// Sets an `async` handler. Every event spawns a new task that awaits the handler.
+///
+/// Errors are logged with [`crate::log`]. Use [`set_event_handler_with`] to get the results.
+pub fn set_event_handler<H, T>(event_name: &str, handler: H, scope: EventScope) -> Subscription
+where
+    H: Handler<T> + 'static,
+    H::Error: std::fmt::Debug,
+    T: DeserializeOwned + 'static,
+{
+    let name = event_name.to_owned();
+
+    let on_result = move |result: Result<H::Response, H::Error>| {
+        if let Err(err) = result {
+            crate::log(format!("event handler {:?} has failed: {:?}", name, err));
+        }
+    };
+
+    set_event_handler_with(event_name, handler, scope, on_result)
 }
 
-pub fn set_event_handler<H, T>(event_name: &str, handler: H, scope: EventScope)
+/// Same as [`set_event_handler`] but passes the result of every call to `on_result`.
+///
+/// # Example
+/// ```rust,ignore
+/// async fn get_balance(source: EventSource, (account,): (String,)) -> Result<u32, BankError> {
+///     /* ... */
+/// }
+///
+/// set_event_handler_with(
+///     "bank:getBalance",
+///     handler_fn(get_balance),
+///     EventScope::Network,
+///     |result| match result {
+///         Ok(balance) => cfx::log(format!("balance: {}", balance)),
+///         Err(err) => cfx::log(format!("bank error: {}", err)),
+///     },
+/// )
+/// .detach();
+/// ```
+pub fn set_event_handler_with<H, T, R>(
+    event_name: &str,
+    handler: H,
+    scope: EventScope,
+    on_result: R,
+) -> Subscription
 where
     H: Handler<T> + 'static,
     T: DeserializeOwned + 'static,
+    R: Fn(Result<H::Response, H::Error>) + 'static,
 {
     let handler = Rc::new(RefCell::new(handler));
+    let on_result = Rc::new(on_result);
+    let name = event_name.to_owned();
 
     let raw_handler = move |raw_event: RawEventRef| {
         let RawEventRef {
             source, payload, ..
         } = raw_event;
 
-        let event = rmp_serde::from_read::<_, T>(payload).ok();
+        let event = decode_payload::<T>(&name, &source, payload)
+            .map_err(|err| report_decode_error(&err))
+            .ok();
 
         if let Some(payload) = event {
-            // let event = EventOwned {
-            //     source: source.to_owned().to_string(),
-            //     payload,
-            // };
-
             let handler = handler.clone();
-            let source = source.to_string();
-
+            let on_result = on_result.clone();
             let _ = crate::runtime::spawn(async move {
-                let _ = handler.borrow_mut().handle(source, payload);
+                // the handler is borrowed only to create the future
+                // so other calls can run while this one is awaited
+                let future = { handler.borrow_mut().handle(source, payload) };
+
+                on_result(future.await);
             });
         }
     };
 
-    EVENTS.with(|events| {
-        let sub = EventSub {
-            scope,
-            handler: EventHandler::Function(Box::new(raw_handler)),
-        };
-
-        let mut events = events.borrow_mut();
-        events.insert(event_name.to_owned(), sub);
-    });
-
-    let _ = crate::invoker::register_resource_as_event_handler(event_name);
+    set_event_handler_raw(event_name, raw_handler, scope)
 }
 
 /// Wrapper around a function that implements [`Handler`]
@@ -251,7 +663,7 @@ pub struct HandlerFn<T> {
 /// #[derive(Debug, Deserialize)]
 /// struct SomeEvent(String)
 ///
-/// async fn handle_event(source: String, event: SomeEvent) -> Result<(), ()> {
+/// async fn handle_event(source: EventSource, event: SomeEvent) -> Result<(), ()> {
 ///     cfx::log(format!("got an event with: {}", event.0));
 ///     Ok(())
 /// }
@@ -263,7 +675,8 @@ pub struct HandlerFn<T> {
 ///     "someEvent",
 ///     handler,
 ///     EventScope::Local,
-/// );
+/// )
+/// .detach();
 /// ```
 pub fn handler_fn<T>(func: T) -> HandlerFn<T> {
     HandlerFn { func }
@@ -271,7 +684,7 @@ pub fn handler_fn<T>(func: T) -> HandlerFn<T> {
 
 impl<T, F, Input, R, E> Handler<Input> for HandlerFn<T>
 where
-    T: FnMut(String, Input) -> F,
+    T: FnMut(EventSource, Input) -> F,
     F: Future<Output = Result<R, E>>,
     Input: DeserializeOwned,
 {
@@ -279,7 +692,7 @@ where
     type Error = E;
     type Future = F;
 
-    fn handle(&mut self, source: String, event: Input) -> Self::Future {
+    fn handle(&mut self, source: EventSource, event: Input) -> Self::Future {
         (self.func)(source, event)
     }
 }
@@ -1,6 +1,6 @@
 //! Export and import from / to another runtimes.
 use crate::{
-    events::Event,
+    events::{Event, Subscription},
     ref_funcs::{ExternRefFunction, RefFunction},
 };
 
@@ -69,9 +69,11 @@ pub fn import_function(resource: &str, export: &str) -> Option<ExternRefFunction
 /// // for example the current resource name is `vectors`
 /// // this export can be called from another resources.
 /// // js: const length = exports.vectors.vecLength({ x: 21.0, y: 5.0, z: 12.5 });
-/// fivem::exports::make_export("vecLength", export);
+/// fivem::exports::make_export("vecLength", export).detach();
 /// ```
-pub fn make_export(export: &str, func: RefFunction) {
+///
+/// The export is removed when the returned [`Subscription`] is dropped.
+pub fn make_export(export: &str, func: RefFunction) -> Subscription {
     #[derive(Serialize, Deserialize)]
     struct GetExport {
         func: ExternRefFunction,
@@ -87,7 +89,7 @@ pub fn make_export(export: &str, func: RefFunction) {
             ext_func.invoke::<(), _>(vec![func.as_extern_ref_func()]);
         },
         crate::events::EventScope::Local,
-    );
+    )
 }
 
 fn export_name(resource: &str, export: &str) -> String {
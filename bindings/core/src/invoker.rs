@@ -1,8 +1,13 @@
 use crate::{
     ref_funcs::{ExternRefFunction, RefFunction},
-    types::{call_result, CharPtr, GuestArg, RetVal, ReturnValue, Vector3},
+    types::{call_result, CharPtr, GuestArg, RetVal, Vector3},
 };
 
+#[cfg(feature = "native")]
+use crate::native::ReturnValue;
+#[cfg(not(feature = "native"))]
+use crate::types::ReturnValue;
+
 use serde::{de::DeserializeOwned, Serialize};
 use std::cell::RefCell;
 
@@ -13,6 +18,10 @@ thread_local! {
 }
 
 mod ffi {
+    #[cfg(feature = "native")]
+    pub use crate::native::ffi::{invoke, invoke_ref_func};
+
+    #[cfg(not(feature = "native"))]
     #[link(wasm_import_module = "cfx")]
     extern "C" {
         pub fn invoke(
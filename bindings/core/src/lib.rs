@@ -1,8 +1,14 @@
+pub mod app;
 pub mod events;
 pub mod exports;
 pub mod invoker;
+pub mod logging;
+pub mod memory;
 pub mod ref_funcs;
 pub mod runtime;
+pub mod sync;
+pub mod task_local;
+pub mod time;
 
 pub mod types {
     //! Utility types to work with WASM runtime.
@@ -19,22 +25,61 @@ pub mod types {
     }
 }
 
+#[cfg(feature = "native")]
+pub mod native;
+
+pub(crate) mod panic;
 pub(crate) mod wasm_impl;
 
+#[cfg(all(test, not(feature = "native")))]
+mod testing;
+
+pub use app::App;
+
+#[doc(hidden)]
+pub mod __private {
+    //! Used by `cfx-macros`.
+    pub use futures::Stream;
+    pub use serde::{de::DeserializeOwned, Serialize};
+}
+
 mod ffi {
+    #[cfg(feature = "native")]
+    pub use crate::native::ffi::script_log;
+
+    #[cfg(not(feature = "native"))]
     #[link(wasm_import_module = "cfx")]
     extern "C" {
         pub fn script_log(message: *const u8);
     }
 }
 
+thread_local! {
+    static LOG_BUFFER: std::cell::RefCell<Vec<u8>> = std::cell::RefCell::new(Vec::new());
+}
+
 /// Logs a message to the CitizenFX server or client
+///
+/// Nul bytes are removed from the message.
 pub fn log<T: AsRef<str>>(message: T) {
-    let msg = message.as_ref();
-    let cstr = std::ffi::CString::new(msg).unwrap();
-    let bytes = cstr.as_bytes_with_nul();
+    let msg = message.as_ref().as_bytes();
 
-    unsafe {
-        ffi::script_log(bytes.as_ptr());
-    }
+    LOG_BUFFER.with(|buffer| {
+        // a message can be logged while another one is being logged (from a panic hook)
+        let mut owned = Vec::new();
+        let mut borrowed = buffer.try_borrow_mut();
+
+        let buffer = match borrowed {
+            Ok(ref mut buffer) => &mut **buffer,
+            Err(_) => &mut owned,
+        };
+
+        buffer.clear();
+        buffer.extend(msg.iter().filter(|&&byte| byte != 0));
+        buffer.push(0);
+
+        unsafe {
+            ffi::script_log(buffer.as_ptr());
+        }
+    });
 }
@@ -0,0 +1,205 @@
+//! Backends for the `log` and `tracing` crates that write to the CitizenFX console.
+//!
+//! Enable the `log` feature for [`init_log`] or the `tracing` feature for [`init_tracing`].
+//! Both use a [`Filter`] with per-module levels, colour levels with CitizenFX `^1`..`^9` codes
+//! and render structured fields as `key=value`.
+//!
+//! # Example
+//! ```rust,ignore
+//! // set my_resource_log "info,my_resource::net=debug"
+//! let filter = cfx::logging::Filter::from_convar("my_resource_log", "info");
+//! cfx::logging::init_log(filter).unwrap();
+//!
+//! log::info!("started");
+//! log::debug!(player = 3; "player has connected");
+//! ```
+#[cfg(any(feature = "log", feature = "tracing"))]
+use std::fmt::{self, Write};
+
+#[cfg(feature = "log")]
+mod logger;
+
+#[cfg(feature = "tracing")]
+mod subscriber;
+
+#[cfg(feature = "log")]
+pub use logger::{init_log, Logger};
+
+#[cfg(feature = "tracing")]
+pub use subscriber::{init_tracing, Subscriber};
+
+/// Verbosity of a message. The most severe is the smallest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn parse(level: &str) -> Option<Option<Level>> {
+        let level = match level.trim().to_ascii_lowercase().as_str() {
+            "off" => None,
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => return None,
+        };
+
+        Some(level)
+    }
+
+    /// A CitizenFX colour code of the level.
+    pub fn color(self) -> &'static str {
+        match self {
+            Level::Error => "^1",
+            Level::Warn => "^3",
+            Level::Info => "^2",
+            Level::Debug => "^5",
+            Level::Trace => "^6",
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// Maximum levels per module, in the `env_logger` syntax: `info,my_resource::net=debug,hyper=off`.
+///
+/// A bare level sets the default, the longest matching module prefix wins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    default: Option<Level>,
+    directives: Vec<(String, Option<Level>)>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            default: Some(Level::Info),
+            directives: Vec::new(),
+        }
+    }
+}
+
+impl Filter {
+    /// Parses directives. Invalid ones are skipped.
+    pub fn parse(directives: &str) -> Filter {
+        let mut filter = Filter {
+            default: Some(Level::Info),
+            directives: Vec::new(),
+        };
+
+        for directive in directives.split(',').map(str::trim) {
+            if directive.is_empty() {
+                continue;
+            }
+
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    if let Some(level) = Level::parse(level) {
+                        filter.directives.push((target.trim().to_owned(), level));
+                    }
+                }
+
+                None => match Level::parse(directive) {
+                    Some(level) => filter.default = level,
+                    None => filter
+                        .directives
+                        .push((directive.to_owned(), Some(Level::Trace))),
+                },
+            }
+        }
+
+        // the longest prefix goes first
+        filter
+            .directives
+            .sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+
+        filter
+    }
+
+    /// Reads directives from a convar.
+    pub fn from_convar(convar: &str, default: &str) -> Filter {
+        let args = &[
+            crate::invoker::Val::String(convar),
+            crate::invoker::Val::String(default),
+        ];
+
+        let directives = crate::invoker::invoke::<String, _>(0x6CCD2564, args) // GET_CONVAR
+            .unwrap_or_else(|_| default.to_owned());
+
+        Filter::parse(&directives)
+    }
+
+    /// Checks if a message of the module should be logged.
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        let max = self
+            .directives
+            .iter()
+            .find(|(module, _)| is_module_prefix(module, target))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default);
+
+        matches!(max, Some(max) if level <= max)
+    }
+
+    /// The most verbose level of all directives.
+    pub fn max_level(&self) -> Option<Level> {
+        self.directives
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, |max, level| max.max(level))
+    }
+}
+
+fn is_module_prefix(module: &str, target: &str) -> bool {
+    match target.strip_prefix(module) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+/// A line of the console: `^2INFO^7 target: message key=value`.
+#[cfg(any(feature = "log", feature = "tracing"))]
+pub(crate) struct Line {
+    buffer: String,
+}
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+#[cfg_attr(not(all(feature = "log", feature = "tracing")), allow(dead_code))]
+impl Line {
+    pub(crate) fn new(level: Level, target: &str) -> Line {
+        let mut buffer = String::with_capacity(128);
+        let _ = write!(buffer, "{}{}^7 {}: ", level.color(), level.as_str(), target);
+
+        Line { buffer }
+    }
+
+    pub(crate) fn push_str(&mut self, text: &str) {
+        self.buffer.push_str(text);
+    }
+
+    pub(crate) fn message(&mut self, message: fmt::Arguments) {
+        let _ = self.buffer.write_fmt(message);
+    }
+
+    pub(crate) fn field(&mut self, key: &str, value: &dyn fmt::Display) {
+        let _ = write!(self.buffer, " {}={}", key, value);
+    }
+
+    pub(crate) fn print(self) {
+        crate::log(&self.buffer);
+    }
+}
@@ -0,0 +1,76 @@
+use super::{Filter, Level, Line};
+use log::kv::{self, Visitor};
+
+/// A [`log::Log`] implementation writing to the CitizenFX console.
+#[derive(Debug)]
+pub struct Logger {
+    filter: Filter,
+}
+
+impl Logger {
+    pub fn new(filter: Filter) -> Logger {
+        Logger { filter }
+    }
+}
+
+fn level(level: log::Level) -> Level {
+    match level {
+        log::Level::Error => Level::Error,
+        log::Level::Warn => Level::Warn,
+        log::Level::Info => Level::Info,
+        log::Level::Debug => Level::Debug,
+        log::Level::Trace => Level::Trace,
+    }
+}
+
+fn level_filter(level: Option<Level>) -> log::LevelFilter {
+    match level {
+        None => log::LevelFilter::Off,
+        Some(Level::Error) => log::LevelFilter::Error,
+        Some(Level::Warn) => log::LevelFilter::Warn,
+        Some(Level::Info) => log::LevelFilter::Info,
+        Some(Level::Debug) => log::LevelFilter::Debug,
+        Some(Level::Trace) => log::LevelFilter::Trace,
+    }
+}
+
+struct Fields<'a>(&'a mut Line);
+
+impl<'a, 'kvs> Visitor<'kvs> for Fields<'a> {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.field(key.as_str(), &value);
+        Ok(())
+    }
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter
+            .enabled(metadata.target(), level(metadata.level()))
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut line = Line::new(level(record.level()), record.target());
+        line.message(*record.args());
+
+        let _ = record.key_values().visit(&mut Fields(&mut line));
+
+        line.print();
+    }
+
+    fn flush(&self) {}
+}
+
+/// Sets [`Logger`] as the logger of the `log` crate.
+pub fn init_log(filter: Filter) -> Result<(), log::SetLoggerError> {
+    let max_level = level_filter(filter.max_level());
+
+    log::set_boxed_logger(Box::new(Logger::new(filter)))?;
+    log::set_max_level(max_level);
+
+    Ok(())
+}
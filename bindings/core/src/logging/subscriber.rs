@@ -0,0 +1,243 @@
+use super::{Filter, Level, Line};
+use std::{
+    cell::RefCell,
+    fmt::{self, Write},
+    num::NonZeroU64,
+};
+use tracing_core::{
+    dispatcher::SetGlobalDefaultError,
+    field::{Field, Visit},
+    span, Dispatch, Event, Interest, LevelFilter, Metadata,
+};
+
+/// A [`tracing_core::Subscriber`] writing to the CitizenFX console.
+///
+/// Events are prefixed with the entered spans: `player{id=3}:load: message key=value`.
+#[derive(Debug)]
+pub struct Subscriber {
+    filter: Filter,
+}
+
+impl Subscriber {
+    pub fn new(filter: Filter) -> Subscriber {
+        Subscriber { filter }
+    }
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: String,
+    refs: usize,
+}
+
+#[derive(Default)]
+struct Spans {
+    slots: Vec<Option<SpanData>>,
+    free: Vec<usize>,
+    stack: Vec<span::Id>,
+}
+
+impl Spans {
+    fn get_mut(&mut self, id: &span::Id) -> Option<&mut SpanData> {
+        let index = id.into_u64() as usize - 1;
+        self.slots.get_mut(index).and_then(|slot| slot.as_mut())
+    }
+}
+
+thread_local! {
+    static SPANS: RefCell<Spans> = RefCell::new(Spans::default());
+}
+
+fn level(level: &tracing_core::Level) -> Level {
+    match *level {
+        tracing_core::Level::ERROR => Level::Error,
+        tracing_core::Level::WARN => Level::Warn,
+        tracing_core::Level::INFO => Level::Info,
+        tracing_core::Level::DEBUG => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+/// Renders fields as ` key=value`, the `message` field is written as is.
+struct Fields<'a> {
+    fields: &'a mut String,
+    message: Option<&'a mut String>,
+}
+
+impl<'a> Visit for Fields<'a> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{}", value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match self.message {
+            Some(ref mut message) if field.name() == "message" => {
+                let _ = write!(message, "{:?}", value);
+            }
+
+            _ => {
+                let _ = write!(self.fields, " {}={:?}", field.name(), value);
+            }
+        }
+    }
+}
+
+impl tracing_core::Subscriber for Subscriber {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if self.enabled(metadata) {
+            Interest::always()
+        } else {
+            Interest::never()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.filter
+            .enabled(metadata.target(), level(metadata.level()))
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        let level = match self.filter.max_level() {
+            None => LevelFilter::OFF,
+            Some(Level::Error) => LevelFilter::ERROR,
+            Some(Level::Warn) => LevelFilter::WARN,
+            Some(Level::Info) => LevelFilter::INFO,
+            Some(Level::Debug) => LevelFilter::DEBUG,
+            Some(Level::Trace) => LevelFilter::TRACE,
+        };
+
+        Some(level)
+    }
+
+    fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+        let mut fields = String::new();
+
+        attrs.record(&mut Fields {
+            fields: &mut fields,
+            message: None,
+        });
+
+        let data = SpanData {
+            name: attrs.metadata().name(),
+            fields,
+            refs: 1,
+        };
+
+        SPANS.with(|spans| {
+            let mut spans = spans.borrow_mut();
+
+            let index = match spans.free.pop() {
+                Some(index) => {
+                    spans.slots[index] = Some(data);
+                    index
+                }
+
+                None => {
+                    spans.slots.push(Some(data));
+                    spans.slots.len() - 1
+                }
+            };
+
+            span::Id::from_non_zero_u64(NonZeroU64::new(index as u64 + 1).unwrap())
+        })
+    }
+
+    fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+        SPANS.with(|spans| {
+            if let Some(data) = spans.borrow_mut().get_mut(span) {
+                values.record(&mut Fields {
+                    fields: &mut data.fields,
+                    message: None,
+                });
+            }
+        });
+    }
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let metadata = event.metadata();
+        let mut line = Line::new(level(metadata.level()), metadata.target());
+
+        SPANS.with(|spans| {
+            let mut spans = spans.borrow_mut();
+            let Spans { slots, stack, .. } = &mut *spans;
+
+            for id in stack.iter() {
+                if let Some(Some(data)) = slots.get(id.into_u64() as usize - 1) {
+                    line.push_str(data.name);
+
+                    if !data.fields.is_empty() {
+                        line.message(format_args!("{{{}}}", data.fields.trim_start()));
+                    }
+
+                    line.push_str(": ");
+                }
+            }
+        });
+
+        let mut message = String::new();
+        let mut fields = String::new();
+
+        event.record(&mut Fields {
+            fields: &mut fields,
+            message: Some(&mut message),
+        });
+
+        line.push_str(&message);
+        line.push_str(&fields);
+        line.print();
+    }
+
+    fn enter(&self, span: &span::Id) {
+        SPANS.with(|spans| spans.borrow_mut().stack.push(span.clone()));
+    }
+
+    fn exit(&self, span: &span::Id) {
+        SPANS.with(|spans| {
+            let mut spans = spans.borrow_mut();
+
+            if let Some(pos) = spans.stack.iter().rposition(|id| id == span) {
+                spans.stack.remove(pos);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &span::Id) -> span::Id {
+        SPANS.with(|spans| {
+            if let Some(data) = spans.borrow_mut().get_mut(span) {
+                data.refs += 1;
+            }
+        });
+
+        span.clone()
+    }
+
+    fn try_close(&self, span: span::Id) -> bool {
+        SPANS.with(|spans| {
+            let mut spans = spans.borrow_mut();
+
+            let closed = match spans.get_mut(&span) {
+                Some(data) => {
+                    data.refs -= 1;
+                    data.refs == 0
+                }
+
+                None => false,
+            };
+
+            if closed {
+                let index = span.into_u64() as usize - 1;
+                spans.slots[index] = None;
+                spans.free.push(index);
+            }
+
+            closed
+        })
+    }
+}
+
+/// Sets [`Subscriber`] as the global default subscriber of `tracing`.
+pub fn init_tracing(filter: Filter) -> Result<(), SetGlobalDefaultError> {
+    tracing_core::dispatcher::set_global_default(Dispatch::new(Subscriber::new(filter)))
+}
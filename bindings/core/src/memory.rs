@@ -0,0 +1,153 @@
+//! Memory usage of the resource.
+//!
+//! Heap counters are collected by [`Counting`]. Install it as the global allocator
+//! around the one the script uses:
+//! ```rust,ignore
+//! #[global_allocator]
+//! static ALLOC: cfx::memory::Counting<std::alloc::System> = cfx::memory::Counting::new(std::alloc::System);
+//! ```
+//!
+//! Buffers that the host allocates with `__cfx_alloc` (event payloads, arguments of ref calls)
+//! are always counted. With the `host-arena` feature they are served from a bump arena that is
+//! reset every time all of them have been freed, so they don't fragment linear memory.
+use crate::runtime::JoinHandle;
+use crate::time::Duration;
+
+use futures::task::SpawnError;
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    fmt,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static DEALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// An allocator that counts allocations made with the inner one. See [`stats`].
+#[derive(Debug, Default)]
+pub struct Counting<A> {
+    inner: A,
+}
+
+impl<A> Counting<A> {
+    pub const fn new(inner: A) -> Counting<A> {
+        Counting { inner }
+    }
+}
+
+fn grow(size: usize) {
+    let live = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+}
+
+fn shrink(size: usize) {
+    LIVE_BYTES.fetch_sub(size, Ordering::Relaxed);
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Counting<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+
+        if !ptr.is_null() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            grow(layout.size());
+        }
+
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+
+        if !ptr.is_null() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            grow(layout.size());
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+
+        DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        shrink(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+
+        if !new_ptr.is_null() {
+            shrink(layout.size());
+            grow(new_size);
+        }
+
+        new_ptr
+    }
+}
+
+/// A snapshot of memory counters. Created by [`stats`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MemoryStats {
+    /// Bytes allocated and not freed yet.
+    pub live_bytes: usize,
+    /// The largest value of `live_bytes` since the start or [`reset_peak`].
+    pub peak_bytes: usize,
+    /// Number of allocations since the start.
+    pub allocations: u64,
+    /// Number of deallocations since the start.
+    pub deallocations: u64,
+    /// Buffers allocated by the host with `__cfx_alloc` and not freed yet.
+    pub host_buffers: usize,
+    /// Size of the live host buffers.
+    pub host_buffer_bytes: usize,
+    /// Capacity of the host buffer arena (`host-arena` feature).
+    pub host_arena_capacity: usize,
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "heap: {} bytes live, {} bytes peak, {} allocations, {} deallocations; host buffers: {} ({} bytes, arena {} bytes)",
+            self.live_bytes,
+            self.peak_bytes,
+            self.allocations,
+            self.deallocations,
+            self.host_buffers,
+            self.host_buffer_bytes,
+            self.host_arena_capacity
+        )
+    }
+}
+
+/// Collects memory counters.
+///
+/// Heap counters are zero unless [`Counting`] is the global allocator.
+pub fn stats() -> MemoryStats {
+    let host = crate::wasm_impl::memory::host_stats();
+
+    MemoryStats {
+        live_bytes: LIVE_BYTES.load(Ordering::Relaxed),
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+        host_buffers: host.buffers,
+        host_buffer_bytes: host.bytes,
+        host_arena_capacity: host.arena_capacity,
+    }
+}
+
+/// Starts counting the peak from the current live bytes.
+pub fn reset_peak() {
+    PEAK_BYTES.store(LIVE_BYTES.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+/// Spawns a task that logs [`stats`] with [`crate::log`] every `period`.
+///
+/// Abort the returned handle to stop logging.
+pub fn log_stats_every(period: Duration) -> Result<JoinHandle<()>, SpawnError> {
+    crate::runtime::spawn_logger("cfx:memory", period, || stats().to_string())
+}
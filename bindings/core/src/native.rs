@@ -0,0 +1,282 @@
+//! Native backend (the `native` feature).
+//!
+//! A script is built as a dynamic library (`crate-type = ["cdylib"]`) for a native host
+//! instead of a WASM module. The script code stays the same:
+//! * the host calls the same exported functions as the WASM runtime
+//!   (`__cfx_on_tick`, `__cfx_on_event`, `__cfx_call_ref` and so on),
+//! * the functions that the WASM runtime provides in the `cfx` import module
+//!   are passed by the host in a [`HostVtable`] with [`__cfx_set_host`].
+//!
+//! The host must call [`__cfx_set_host`] before any other export and call every export
+//! from the same thread. Pointers in the arguments are plain native pointers,
+//! including the return buffer of `invoke` (see [`ReturnValue`]).
+use crate::types::{GuestArg, RetVal, ReturnType};
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// Version of [`HostVtable`]. Bumped on every change of the layout.
+pub const HOST_VTABLE_VERSION: u32 = 3;
+
+/// A buffer for a return value of [`HostVtable::invoke`].
+///
+/// Same as [`crate::types::ReturnValue`] but with a pointer-width buffer,
+/// the WASM one stores a 32-bit offset in the guest memory.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct ReturnValue {
+    pub rettype: ReturnType,
+    pub buffer: *mut u8,
+    pub capacity: usize,
+}
+
+impl ReturnValue {
+    pub(crate) fn new<T: RetVal>(buf: &[u8]) -> ReturnValue {
+        ReturnValue {
+            rettype: T::IDENT,
+            buffer: buf.as_ptr() as *mut u8,
+            capacity: buf.len(),
+        }
+    }
+}
+
+/// Functions provided by a native host. Mirrors the `cfx` import module of the WASM runtime.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct HostVtable {
+    /// Must be [`HOST_VTABLE_VERSION`].
+    pub version: u32,
+
+    pub invoke: unsafe extern "C" fn(
+        hash: u64,
+        ptr: *const GuestArg,
+        len: usize,
+        retval: *const ReturnValue,
+    ) -> i32,
+
+    pub invoke_ref_func: unsafe extern "C" fn(
+        ref_name: *const i8,
+        args: *const u8,
+        args_len: usize,
+        buffer: *mut u8,
+        buffer_capacity: usize,
+    ) -> i32,
+
+    pub canonicalize_ref:
+        unsafe extern "C" fn(ref_idx: u32, buffer: *mut i8, buffer_size: usize) -> i32,
+
+    pub script_log: unsafe extern "C" fn(message: *const u8),
+
+    /// Nanoseconds since an arbitrary point in the past. Never goes backwards.
+    ///
+    /// Only called with the `host-clock` feature.
+    pub monotonic_time: unsafe extern "C" fn() -> u64,
+}
+
+static HOST: AtomicPtr<HostVtable> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Sets the host functions. The vtable is copied so the host doesn't have to keep it alive.
+///
+/// The host can be set only once. Returns `false` if it is already set, the pointer is null
+/// or the version doesn't match [`HOST_VTABLE_VERSION`].
+///
+/// # Safety
+/// `vtable` must be null or point to a valid [`HostVtable`].
+#[no_mangle]
+pub unsafe extern "C" fn __cfx_set_host(vtable: *const HostVtable) -> bool {
+    let vtable = match vtable.as_ref() {
+        Some(vtable) if vtable.version == HOST_VTABLE_VERSION => *vtable,
+        _ => return false,
+    };
+
+    let new = Box::into_raw(Box::new(vtable));
+
+    // the vtable is never freed, `host` hands out `'static` references to it
+    let set = HOST.compare_exchange(
+        std::ptr::null_mut(),
+        new,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    );
+
+    if set.is_err() {
+        drop(Box::from_raw(new));
+    }
+
+    set.is_ok()
+}
+
+fn host() -> &'static HostVtable {
+    // unit tests outside of this module don't know about the host
+    #[cfg(test)]
+    tests::set_host();
+
+    let host = HOST.load(Ordering::Acquire);
+
+    unsafe { host.as_ref() }.expect("the host must call __cfx_set_host before anything else")
+}
+
+/// Same signatures as the `cfx` imports of the WASM backend, except for [`ReturnValue`].
+pub(crate) mod ffi {
+    use super::{host, ReturnValue};
+    use crate::types::GuestArg;
+
+    pub unsafe fn invoke(
+        hash: u64,
+        ptr: *const GuestArg,
+        len: usize,
+        retval: *const ReturnValue,
+    ) -> i32 {
+        (host().invoke)(hash, ptr, len, retval)
+    }
+
+    pub unsafe fn invoke_ref_func(
+        ref_name: *const i8,
+        args: *const u8,
+        args_len: usize,
+        buffer: *mut u8,
+        buffer_capacity: usize,
+    ) -> i32 {
+        (host().invoke_ref_func)(ref_name, args, args_len, buffer, buffer_capacity)
+    }
+
+    pub unsafe fn canonicalize_ref(ref_idx: u32, buffer: *mut i8, buffer_size: usize) -> i32 {
+        (host().canonicalize_ref)(ref_idx, buffer, buffer_size)
+    }
+
+    pub unsafe fn script_log(message: *const u8) {
+        (host().script_log)(message)
+    }
+
+    #[cfg(feature = "host-clock")]
+    pub unsafe fn monotonic_time() -> u64 {
+        (host().monotonic_time)()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        invoker::{invoke, InvokeError, Val},
+        types::call_result,
+    };
+    use std::{
+        ffi::CStr,
+        sync::{Once, OnceLock},
+        time::Instant,
+    };
+
+    const DOUBLE: u64 = 1;
+    const GREET: u64 = 2;
+
+    unsafe extern "C" fn host_invoke(
+        hash: u64,
+        ptr: *const GuestArg,
+        len: usize,
+        retval: *const ReturnValue,
+    ) -> i32 {
+        let args = std::slice::from_raw_parts(ptr, len);
+        let retval = &*retval;
+
+        let bytes = match (hash, retval.rettype) {
+            (DOUBLE, ReturnType::Number) => {
+                let value = *(args[0].value as *const i32);
+                (value * 2).to_ne_bytes().to_vec()
+            }
+
+            (GREET, ReturnType::String) => {
+                let name = CStr::from_ptr(args[0].value as *const _);
+                format!("hello, {}", name.to_str().unwrap()).into_bytes()
+            }
+
+            _ => return call_result::WRONG_ARGS,
+        };
+
+        if bytes.len() > retval.capacity {
+            return call_result::SMALL_RETURN_BUFFER;
+        }
+
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), retval.buffer, bytes.len());
+
+        bytes.len() as _
+    }
+
+    unsafe extern "C" fn host_invoke_ref_func(
+        _: *const i8,
+        _: *const u8,
+        _: usize,
+        _: *mut u8,
+        _: usize,
+    ) -> i32 {
+        call_result::NULL_RESULT
+    }
+
+    unsafe extern "C" fn host_canonicalize_ref(_: u32, _: *mut i8, _: usize) -> i32 {
+        0
+    }
+
+    unsafe extern "C" fn host_script_log(message: *const u8) {
+        println!("{}", CStr::from_ptr(message as _).to_string_lossy());
+    }
+
+    unsafe extern "C" fn host_monotonic_time() -> u64 {
+        static START: OnceLock<Instant> = OnceLock::new();
+
+        START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+    }
+
+    fn vtable(version: u32) -> HostVtable {
+        HostVtable {
+            version,
+            invoke: host_invoke,
+            invoke_ref_func: host_invoke_ref_func,
+            canonicalize_ref: host_canonicalize_ref,
+            script_log: host_script_log,
+            monotonic_time: host_monotonic_time,
+        }
+    }
+
+    pub(super) fn set_host() {
+        static SET: Once = Once::new();
+
+        SET.call_once(|| assert!(unsafe { __cfx_set_host(&vtable(HOST_VTABLE_VERSION)) }));
+    }
+
+    #[test]
+    fn host_is_set_once() {
+        set_host();
+
+        assert!(!unsafe { __cfx_set_host(&vtable(HOST_VTABLE_VERSION)) });
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        assert!(!unsafe { __cfx_set_host(&vtable(HOST_VTABLE_VERSION - 1)) });
+        assert!(!unsafe { __cfx_set_host(std::ptr::null()) });
+    }
+
+    #[test]
+    fn number_round_trip() {
+        set_host();
+
+        assert_eq!(invoke::<i32, _>(DOUBLE, &[Val::Integer(21)]).unwrap(), 42);
+    }
+
+    #[test]
+    fn string_round_trip() {
+        set_host();
+
+        let greeting = invoke::<String, _>(GREET, &[Val::String("native")]).unwrap();
+        assert_eq!(greeting, "hello, native");
+    }
+
+    #[test]
+    fn host_errors_are_returned() {
+        set_host();
+
+        let result = invoke::<String, _>(DOUBLE, &[Val::Integer(1)]);
+        assert!(matches!(
+            result,
+            Err(InvokeError::Code(call_result::WRONG_ARGS))
+        ));
+    }
+}
@@ -0,0 +1,184 @@
+//! Panic boundaries for tasks and callbacks called by CitizenFX.
+//!
+//! Every task, event handler and ref function runs inside [`catch`] and every panic
+//! is logged with the name of the failing task or callback.
+//!
+//! What happens next depends on the panic strategy:
+//! * with `panic = "unwind"` the panic is caught and only the failing task / call is dropped,
+//! * with `panic = "abort"` (the only strategy of stable `wasm32` targets) nothing can be caught
+//!   and the whole WASM instance traps right after the panic has been logged.
+//!
+//! A panic hook set by the script is kept and called after the one that logs the panic.
+//!
+//! See the readme on how to build scripts with `panic = "unwind"`.
+use std::{
+    any::Any,
+    cell::RefCell,
+    panic::{AssertUnwindSafe, Location},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// A name of a running boundary. Its lifetime is erased while it is on [`BOUNDARIES`].
+type BoundaryName = *const (dyn Fn() -> String + 'static);
+
+thread_local! {
+    /// Names of the running boundaries, the innermost is the last.
+    static BOUNDARIES: RefCell<Vec<BoundaryName>> = RefCell::new(Vec::new());
+    static LAST_PANIC: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Removes a boundary from [`BOUNDARIES`] even if the call unwinds.
+struct Enter;
+
+impl Enter {
+    fn new(name: &dyn Fn() -> String) -> Enter {
+        // SAFETY: the name is removed in `drop` before the borrow ends
+        let name: BoundaryName = unsafe { std::mem::transmute(name) };
+        BOUNDARIES.with(|names| names.borrow_mut().push(name));
+
+        Enter
+    }
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        BOUNDARIES.with(|names| names.borrow_mut().pop());
+    }
+}
+
+/// Describes the innermost running boundary.
+fn current_boundary() -> Option<String> {
+    let name = BOUNDARIES.with(|names| names.borrow().last().copied())?;
+
+    // SAFETY: names are alive while they are on the stack
+    Some(unsafe { (*name)() })
+}
+
+/// Set while our hook is installed, reset when `catch` sees that a script has replaced it.
+static HOOK_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the hook that logs panics. A hook that was installed before is called after it.
+fn install_hook() {
+    if HOOK_INSTALLED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    let previous = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        log_panic(info.payload(), info.location());
+        previous(info);
+    }));
+}
+
+fn log_panic(payload: &(dyn Any + Send), location: Option<&Location>) {
+    let message = payload_message(payload);
+
+    let message = match location {
+        Some(location) => format!("'{}' at {}", message, describe(location)),
+        None => format!("'{}'", message),
+    };
+
+    if cfg!(panic = "unwind") && in_boundary() {
+        // logged by `catch` that knows the name
+        LAST_PANIC.with(|last| *last.borrow_mut() = Some(message));
+    } else {
+        match current_boundary() {
+            Some(name) => crate::log(format!("{} panicked with {}", name, message)),
+            None => crate::log(format!("panicked with {}", message)),
+        }
+    }
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.as_str()
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+fn in_boundary() -> bool {
+    BOUNDARIES.with(|names| !names.borrow().is_empty())
+}
+
+fn describe(location: &Location) -> String {
+    format!(
+        "{}:{}:{}",
+        location.file(),
+        location.line(),
+        location.column()
+    )
+}
+
+/// Calls `func` and catches a panic if it happens (only with `panic = "unwind"`).
+///
+/// `name` is called only on a panic to describe what has failed, e.g. `event handler "playerDropped"`.
+pub(crate) fn catch<R, N, F>(name: N, func: F) -> Option<R>
+where
+    N: Fn() -> String,
+    F: FnOnce() -> R,
+{
+    install_hook();
+
+    let result = {
+        let _enter = Enter::new(&name);
+        std::panic::catch_unwind(AssertUnwindSafe(func))
+    };
+
+    match result {
+        Ok(result) => Some(result),
+        Err(payload) => {
+            let message = LAST_PANIC.with(|last| last.borrow_mut().take());
+
+            let message = message.unwrap_or_else(|| {
+                // the script has replaced our hook, put it back in front of the new one
+                HOOK_INSTALLED.store(false, Ordering::Release);
+                install_hook();
+
+                format!("'{}'", payload_message(&*payload))
+            });
+
+            crate::log(format!("{} panicked with {}", name(), message));
+
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{atomic::AtomicUsize, Arc};
+
+    #[test]
+    fn script_hooks_keep_being_called() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let script_calls = calls.clone();
+
+        // a hook installed by a script after ours
+        std::panic::set_hook(Box::new(move |_| {
+            script_calls.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        assert_eq!(catch(|| "first".to_owned(), || panic!("first")), None::<()>);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // our hook is back in front of the script one
+        let result = std::panic::catch_unwind(|| {
+            let _enter = Enter::new(&|| "second".to_owned());
+            panic!("second");
+        });
+
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let message = LAST_PANIC.with(|last| last.borrow_mut().take()).unwrap();
+        assert!(message.starts_with("'second' at "), "{}", message);
+
+        let _ = std::panic::take_hook();
+        HOOK_INSTALLED.store(false, Ordering::Release);
+    }
+}
@@ -7,14 +7,19 @@ use std::{
 use crate::wasm_impl::ref_funcs::{canonicalize_ref, HANDLERS, REF_IDX};
 
 pub(crate) struct InnerRefFunction {
-    pub(crate) _idx: u32,
-    pub(crate) func: Box<dyn Fn(&[u8], &RefCell<Vec<u8>>)>,
+    pub(crate) idx: u32,
+    pub(crate) func: Box<dyn Fn(&[u8], &RefCell<Vec<u8>>) -> bool>,
     pub(crate) refs: Cell<i32>,
 }
 
 impl InnerRefFunction {
-    pub(crate) fn handle(&self, input: &[u8], output: &RefCell<Vec<u8>>) {
-        (self.func)(input, output);
+    /// Calls the function. Returns `false` if the call has failed (bad input or a panic).
+    pub(crate) fn handle(&self, input: &[u8], output: &RefCell<Vec<u8>>) -> bool {
+        crate::panic::catch(
+            || format!("ref function #{}", self.idx),
+            || (self.func)(input, output),
+        )
+        .unwrap_or(false)
     }
 }
 
@@ -78,21 +83,30 @@ impl RefFunction {
         let name = canonicalize_ref(idx);
 
         let func = move |input: &[u8], out_buf: &RefCell<Vec<u8>>| {
-            let input = rmp_serde::decode::from_read(input).unwrap();
-            let out = handler(input);
-            {
-                let mut out_buf = out_buf.borrow_mut();
-
-                unsafe {
-                    out_buf.set_len(0);
+            let input = match rmp_serde::decode::from_read(input) {
+                Ok(input) => input,
+                Err(err) => {
+                    crate::log(format!(
+                        "ref function #{} failed to decode arguments: {}",
+                        idx, err
+                    ));
+
+                    return false;
                 }
+            };
+
+            let out = handler(input);
+            let mut out_buf = out_buf.borrow_mut();
 
-                let _ = rmp_serde::encode::write_named(&mut *out_buf, &out);
+            unsafe {
+                out_buf.set_len(0);
             }
+
+            rmp_serde::encode::write_named(&mut *out_buf, &out).is_ok()
         };
 
         let inner = InnerRefFunction {
-            _idx: idx,
+            idx,
             func: Box::new(func),
             refs: Cell::new(0),
         };
@@ -131,10 +145,12 @@ impl RefFunction {
 
                 out_buf.extend(out.iter());
             }
+
+            true
         };
 
         let inner = InnerRefFunction {
-            _idx: idx,
+            idx,
             func: Box::new(func),
             refs: Cell::new(0),
         };
@@ -1,17 +1,598 @@
 use futures::{
     channel::oneshot,
+    future::{AbortHandle, Abortable, Either},
+    stream::FuturesUnordered,
     task::{LocalSpawnExt, SpawnError},
-    Future, TryFutureExt,
+    Future, FutureExt, Stream, StreamExt, TryFutureExt,
 };
 
-use std::time::{Duration, Instant};
+use std::{
+    ops::{Add, Sub},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::time::{Duration, Instant};
+use crate::wasm_impl::runtime::{
+    wake_at_next_tick, TaskInfo, BACKGROUND_SPAWNER, BUDGET, LAST_TICK_POLLS, NEXT_TASK_ID,
+    SPAWNER, STOPPING, STOP_CALLBACKS, STOP_SIGNALS, TASKS, TICK, TICK_POLLS, TIMERS,
+};
+use crate::wasm_impl::timers::TimerKey;
+
+/// An error returned by [`JoinHandle`] when a task didn't finish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The task was aborted with [`JoinHandle::abort`] or dropped by the runtime.
+    Cancelled,
+    /// The task has panicked. The panic was logged and the task was dropped.
+    ///
+    /// Only returned by builds with `panic = "unwind"`, otherwise a panic traps the instance.
+    Panicked,
+}
+
+/// An owned permission to await a spawned task.
+///
+/// Dropping a handle detaches the task (it keeps running) unless [`JoinHandle::abort_on_drop`] is set.
+///
+/// # Example
+/// ```rust,ignore
+/// let handle = cfx::runtime::spawn(show_something(event.into_inner()))?.abort_on_drop(true);
+///
+/// // a player has left, stop everything that was running for them
+/// drop(handle);
+/// ```
+#[derive(Debug)]
+pub struct JoinHandle<T> {
+    output: oneshot::Receiver<Result<T, JoinError>>,
+    abort: AbortHandle,
+    abort_on_drop: bool,
+}
+
+impl<T> JoinHandle<T> {
+    /// Aborts the task. It will be dropped at the next executor pass.
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+
+    /// Aborts the task when the handle is dropped.
+    pub fn abort_on_drop(mut self, abort_on_drop: bool) -> Self {
+        self.abort_on_drop = abort_on_drop;
+        self
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.output
+            .poll_unpin(cx)
+            .map(|output| output.unwrap_or(Err(JoinError::Cancelled)))
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if self.abort_on_drop {
+            self.abort.abort();
+        }
+    }
+}
+
+/// How much work the executor may do in a single tick.
+///
+/// Tasks that are still ready when the budget runs out are moved to the next tick.
+/// [`Priority::Background`] tasks are moved once half of the budget is spent.
+/// At least one task is polled every tick, even with an empty budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TickBudget {
+    /// Poll every ready task until the executor has stalled.
+    #[default]
+    Unlimited,
+    /// Maximum number of task polls per tick.
+    Polls(u32),
+    /// Maximum time spent polling tasks per tick.
+    Time(Duration),
+}
+
+/// Sets the per-tick execution budget of the executor. Default is [`TickBudget::Unlimited`].
+///
+/// # Example
+/// ```rust,ignore
+/// // don't spend more than 2ms of a server frame in this resource
+/// cfx::runtime::set_tick_budget(TickBudget::Time(Duration::from_millis(2)));
+/// ```
+pub fn set_tick_budget(budget: TickBudget) {
+    BUDGET.with(|state| state.borrow_mut().limit = budget);
+}
+
+/// Returns the number of ticks where the budget ran out and some tasks were moved to the next tick.
+pub fn budget_overruns() -> u64 {
+    BUDGET.with(|budget| budget.borrow().overruns)
+}
+
+/// A priority class of a spawned task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Priority {
+    /// Per-frame work such as drawing and input. Polled first and may use the whole tick budget.
+    #[default]
+    FrameCritical,
+    /// Bulk work such as saving or recomputing leaderboards. Polled after frame-critical tasks
+    /// and moved to the next tick once half of the tick budget is spent.
+    Background,
+}
+
+/// A spawned task: polls the inner future inside a panic boundary
+/// and within the tick budget. Completes with `None` if it has panicked.
+struct Task<Fut> {
+    id: u64,
+    priority: Priority,
+    future: Fut,
+}
+
+impl<Fut: Future> Future for Task<Fut> {
+    type Output = Option<Fut::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if !BUDGET.with(|budget| budget.borrow_mut().acquire(self.priority)) {
+            wake_at_next_tick(cx.waker());
+            return Poll::Pending;
+        }
+
+        let id = self.id;
+        let started = Instant::now();
+
+        // SAFETY: `future` is never moved out of `self`.
+        let future = unsafe { self.map_unchecked_mut(|this| &mut this.future) };
+        let result = crate::panic::catch(|| describe_task(id), || future.poll(cx));
+
+        let elapsed = started.elapsed();
+
+        BUDGET.with(|budget| budget.borrow_mut().spend(elapsed));
+        TICK_POLLS.with(|polls| polls.set(polls.get() + 1));
 
-pub(crate) use crate::wasm_impl::runtime::LOCAL_POOL;
-use crate::wasm_impl::runtime::{SPAWNER, TIMERS};
+        TASKS.with(|tasks| {
+            if let Some(info) = tasks.borrow_mut().get_mut(&id) {
+                info.polls += 1;
+                info.busy += elapsed;
+                info.slowest_poll = info.slowest_poll.max(elapsed);
+            }
+        });
+
+        match result {
+            Some(Poll::Ready(output)) => Poll::Ready(Some(output)),
+            Some(Poll::Pending) => Poll::Pending,
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+impl<Fut> Drop for Task<Fut> {
+    fn drop(&mut self) {
+        let id = self.id;
+
+        let _ = TASKS.try_with(|tasks| {
+            if let Ok(mut tasks) = tasks.try_borrow_mut() {
+                tasks.remove(&id);
+            }
+        });
+    }
+}
+
+fn describe_task(id: u64) -> String {
+    let name = TASKS.with(|tasks| tasks.borrow().get(&id).and_then(|info| info.name.clone()));
+
+    match name {
+        Some(name) => format!("task {:?}", name),
+        None => format!("task #{}", id),
+    }
+}
 
 /// Spawns a new local future that will be polled at next tick or a new event comming
-pub fn spawn<Fut: Future<Output = ()> + 'static>(future: Fut) -> Result<(), SpawnError> {
-    SPAWNER.with(|sp| sp.borrow().spawn_local(future))
+///
+/// Returns a [`JoinHandle`] that can be awaited for the output of the future or used to abort it.
+/// If the future panics the panic is logged with the task name. With `panic = "unwind"`
+/// only this task is dropped, otherwise the whole instance traps.
+pub fn spawn<T, Fut>(future: Fut) -> Result<JoinHandle<T>, SpawnError>
+where
+    T: 'static,
+    Fut: Future<Output = T> + 'static,
+{
+    TaskBuilder::new().spawn(future)
+}
+
+/// Same as [`spawn`] but gives the task a name that is shown in [`stats`] and panic messages.
+///
+/// # Example
+/// ```rust,ignore
+/// let _ = cfx::runtime::spawn_named("connections", handle_connections());
+/// ```
+pub fn spawn_named<T, Fut>(name: &str, future: Fut) -> Result<JoinHandle<T>, SpawnError>
+where
+    T: 'static,
+    Fut: Future<Output = T> + 'static,
+{
+    TaskBuilder::new().name(name).spawn(future)
+}
+
+/// Same as [`spawn`] but with [`Priority::Background`].
+pub fn spawn_background<T, Fut>(future: Fut) -> Result<JoinHandle<T>, SpawnError>
+where
+    T: 'static,
+    Fut: Future<Output = T> + 'static,
+{
+    TaskBuilder::new()
+        .priority(Priority::Background)
+        .spawn(future)
+}
+
+/// Configures a task before spawning it.
+///
+/// # Example
+/// ```rust,ignore
+/// let _ = TaskBuilder::new()
+///     .name("leaderboard")
+///     .priority(Priority::Background)
+///     .spawn(recompute_leaderboard());
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct TaskBuilder {
+    name: Option<String>,
+    priority: Priority,
+}
+
+impl TaskBuilder {
+    /// Creates a builder for an unnamed [`Priority::FrameCritical`] task.
+    pub fn new() -> TaskBuilder {
+        TaskBuilder::default()
+    }
+
+    /// Sets the name shown in [`stats`] and panic messages.
+    pub fn name(mut self, name: &str) -> TaskBuilder {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    /// Sets the priority class.
+    pub fn priority(mut self, priority: Priority) -> TaskBuilder {
+        self.priority = priority;
+        self
+    }
+
+    /// Spawns the task. See [`spawn`].
+    pub fn spawn<T, Fut>(self, future: Fut) -> Result<JoinHandle<T>, SpawnError>
+    where
+        T: 'static,
+        Fut: Future<Output = T> + 'static,
+    {
+        spawn_task(self, future)
+    }
+}
+
+fn spawn_task<T, Fut>(builder: TaskBuilder, future: Fut) -> Result<JoinHandle<T>, SpawnError>
+where
+    T: 'static,
+    Fut: Future<Output = T> + 'static,
+{
+    let TaskBuilder { name, priority } = builder;
+
+    let (tx, rx) = oneshot::channel();
+    let (abort, registration) = AbortHandle::new_pair();
+
+    let id = NEXT_TASK_ID.with(|next| next.replace(next.get() + 1));
+
+    let future = Task {
+        id,
+        priority,
+        future: Abortable::new(future, registration),
+    };
+
+    let task = future.map(move |output| match output {
+        Some(Ok(output)) => {
+            let _ = tx.send(Ok(output));
+        }
+
+        Some(Err(_aborted)) => (),
+
+        None => {
+            let _ = tx.send(Err(JoinError::Panicked));
+        }
+    });
+
+    TASKS.with(|tasks| {
+        let info = TaskInfo {
+            name,
+            priority,
+            ..Default::default()
+        };
+
+        tasks.borrow_mut().insert(id, info);
+    });
+
+    match priority {
+        Priority::FrameCritical => SPAWNER.with(|sp| sp.borrow().spawn_local(task))?,
+        Priority::Background => BACKGROUND_SPAWNER.with(|sp| sp.borrow().spawn_local(task))?,
+    }
+
+    crate::wasm_impl::runtime::mark_has_work();
+
+    Ok(JoinHandle {
+        output: rx,
+        abort,
+        abort_on_drop: false,
+    })
+}
+
+/// An error returned by [`TaskGroup`] when a child task has failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskGroupError<E> {
+    /// The task has returned an error.
+    Task(E),
+    /// The task has been cancelled or has panicked.
+    Join(JoinError),
+}
+
+/// A set of tasks owned by a parent.
+///
+/// Children are aborted when the group is dropped, and [`TaskGroup::join`] aborts them
+/// as soon as one of them fails. Use it for helper tasks that must not outlive their parent.
+///
+/// # Example
+/// ```rust,ignore
+/// async fn handle_player(event: PlayerConnecting) -> Result<(), Error> {
+///     let mut group = TaskGroup::new();
+///
+///     group.spawn(update_deferrals(event.deferrals))?;
+///     group.spawn(load_profile(event.player_name))?;
+///
+///     // the first error cancels the other task
+///     group.join().await?;
+///
+///     Ok(())
+/// }
+/// ```
+pub struct TaskGroup<T, E> {
+    tasks: FuturesUnordered<Child<T, E>>,
+    next_index: usize,
+}
+
+struct Child<T, E> {
+    index: usize,
+    handle: JoinHandle<Result<T, E>>,
+}
+
+impl<T, E> Future for Child<T, E> {
+    type Output = (usize, Result<T, TaskGroupError<E>>);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let output = futures::ready!(self.handle.poll_unpin(cx));
+
+        let output = match output {
+            Ok(Ok(output)) => Ok(output),
+            Ok(Err(err)) => Err(TaskGroupError::Task(err)),
+            Err(err) => Err(TaskGroupError::Join(err)),
+        };
+
+        Poll::Ready((self.index, output))
+    }
+}
+
+impl<T: 'static, E: 'static> TaskGroup<T, E> {
+    /// Creates an empty group.
+    pub fn new() -> TaskGroup<T, E> {
+        TaskGroup {
+            tasks: FuturesUnordered::new(),
+            next_index: 0,
+        }
+    }
+
+    /// Spawns a child task owned by the group.
+    pub fn spawn<Fut>(&mut self, future: Fut) -> Result<(), SpawnError>
+    where
+        Fut: Future<Output = Result<T, E>> + 'static,
+    {
+        let handle = spawn(future)?.abort_on_drop(true);
+
+        self.tasks.push(Child {
+            index: self.next_index,
+            handle,
+        });
+
+        self.next_index += 1;
+
+        Ok(())
+    }
+
+    /// The number of children that haven't been joined yet.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Checks if there are no children left.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Aborts all children.
+    pub fn abort_all(&mut self) {
+        for child in self.tasks.iter() {
+            child.handle.abort();
+        }
+    }
+
+    /// Waits for the next child to finish. Returns `None` if the group is empty.
+    pub async fn join_next(&mut self) -> Option<Result<T, TaskGroupError<E>>> {
+        self.tasks.next().await.map(|(_, output)| output)
+    }
+
+    /// Waits for all children and returns their outputs in order of spawning.
+    ///
+    /// The first failure aborts the remaining children and is returned.
+    pub async fn join(mut self) -> Result<Vec<T>, TaskGroupError<E>> {
+        let mut outputs = Vec::with_capacity(self.tasks.len());
+        outputs.resize_with(self.next_index, || None);
+
+        while let Some((index, output)) = self.tasks.next().await {
+            match output {
+                Ok(output) => outputs[index] = Some(output),
+                Err(err) => {
+                    self.abort_all();
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(outputs.into_iter().flatten().collect())
+    }
+}
+
+impl<T: 'static, E: 'static> Default for TaskGroup<T, E> {
+    fn default() -> Self {
+        TaskGroup::new()
+    }
+}
+
+impl<T, E> std::fmt::Debug for TaskGroup<T, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TaskGroup")
+            .field("tasks", &self.tasks.len())
+            .finish()
+    }
+}
+
+/// Statistics of a single task. Part of [`RuntimeStats`].
+#[derive(Debug, Clone)]
+pub struct TaskStats {
+    /// Unique id of the task.
+    pub id: u64,
+    /// Name given by [`spawn_named`].
+    pub name: Option<String>,
+    /// Priority class of the task.
+    pub priority: Priority,
+    /// How many times the task has been polled.
+    pub polls: u64,
+    /// Total time spent polling the task.
+    pub busy: Duration,
+    /// The longest single poll.
+    pub slowest_poll: Duration,
+}
+
+/// A snapshot of the executor state. Created by [`stats`].
+#[derive(Debug, Clone)]
+pub struct RuntimeStats {
+    /// Number of tasks that haven't completed yet.
+    pub live_tasks: usize,
+    /// Task polls during the previous tick (including events between the ticks).
+    pub polls_last_tick: u32,
+    /// Number of timers that haven't completed yet.
+    pub pending_timers: usize,
+    /// See [`budget_overruns`].
+    pub budget_overruns: u64,
+    /// Live tasks sorted by busy time, the busiest first.
+    pub tasks: Vec<TaskStats>,
+    /// Events received by [`crate::events::subscribe`] streams but not consumed yet.
+    pub event_queues: Vec<(String, usize)>,
+}
+
+impl RuntimeStats {
+    /// The task with the longest single poll.
+    pub fn slowest_task(&self) -> Option<&TaskStats> {
+        self.tasks.iter().max_by_key(|task| task.slowest_poll)
+    }
+}
+
+impl std::fmt::Display for RuntimeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "tasks: {} polls last tick: {} timers: {} budget overruns: {}",
+            self.live_tasks, self.polls_last_tick, self.pending_timers, self.budget_overruns
+        )?;
+
+        for task in &self.tasks {
+            let name = match task.name {
+                Some(ref name) => name.clone(),
+                None => format!("#{}", task.id),
+            };
+
+            writeln!(
+                f,
+                "  task {} ({:?}): polls: {} busy: {:?} slowest poll: {:?}",
+                name, task.priority, task.polls, task.busy, task.slowest_poll
+            )?;
+        }
+
+        for (event, queued) in &self.event_queues {
+            writeln!(f, "  event {:?}: {} queued", event, queued)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Collects statistics of the executor.
+///
+/// # Example
+/// ```rust,ignore
+/// let stats = cfx::runtime::stats();
+///
+/// if let Some(task) = stats.slowest_task() {
+///     cfx::log(format!("slowest task: {:?} ({:?})", task.name, task.slowest_poll));
+/// }
+/// ```
+pub fn stats() -> RuntimeStats {
+    let mut tasks = TASKS.with(|tasks| {
+        tasks
+            .borrow()
+            .iter()
+            .map(|(id, info)| TaskStats {
+                id: *id,
+                name: info.name.clone(),
+                priority: info.priority,
+                polls: info.polls,
+                busy: info.busy,
+                slowest_poll: info.slowest_poll,
+            })
+            .collect::<Vec<_>>()
+    });
+
+    tasks.sort_by_key(|task| std::cmp::Reverse(task.busy));
+
+    RuntimeStats {
+        live_tasks: tasks.len(),
+        polls_last_tick: LAST_TICK_POLLS.with(|polls| polls.get()),
+        pending_timers: pending_timers(),
+        budget_overruns: budget_overruns(),
+        tasks,
+        event_queues: crate::wasm_impl::events::queue_depths(),
+    }
+}
+
+/// Spawns a task that logs [`stats`] with [`crate::log`] every `period`.
+///
+/// Abort the returned handle to stop logging.
+pub fn log_stats_every(period: Duration) -> Result<JoinHandle<()>, SpawnError> {
+    spawn_logger("cfx:stats", period, || stats().to_string())
+}
+
+/// Spawns a task named `name` that logs the result of `message` every `period`.
+pub(crate) fn spawn_logger(
+    name: &str,
+    period: Duration,
+    message: fn() -> String,
+) -> Result<JoinHandle<()>, SpawnError> {
+    spawn_named(name, async move {
+        let mut interval = interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        // the first tick is immediate
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            crate::log(message());
+        }
+    })
 }
 
 /// Stops execution for duration (doesn't block CitizenFX).
@@ -46,15 +627,555 @@ pub fn spawn<Fut: Future<Output = ()> + 'static>(future: Fut) -> Result<(), Spaw
 ///     // event.deferrals.done.invoke::<(), _>(vec![done_msg]);
 /// }
 /// ```
-pub fn sleep_for(duration: Duration) -> impl Future<Output = ()> {
+pub fn sleep_for(duration: Duration) -> Sleep {
     let instant = Instant::now().checked_add(duration).unwrap();
+    sleep_until(instant)
+}
+
+/// Stops execution until the given instant (doesn't block CitizenFX).
+///
+/// An instant in the past completes at the next tick.
+pub fn sleep_until(instant: Instant) -> Sleep {
+    let key = TIMERS.with(|timers| timers.borrow_mut().insert(instant));
+    Sleep { key }
+}
+
+/// A future returned by [`sleep_for`] and [`sleep_until`].
+///
+/// Dropping it removes the timer from the queue.
+#[derive(Debug)]
+pub struct Sleep {
+    key: TimerKey,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        TIMERS.with(|timers| timers.borrow_mut().poll(self.key, cx.waker()))
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        let key = self.key;
+
+        let _ = TIMERS.try_with(|timers| {
+            if let Ok(mut timers) = timers.try_borrow_mut() {
+                timers.cancel(key);
+            }
+        });
+    }
+}
+
+/// A source of time other than the resource's own monotonic clock,
+/// for example `GetGameTimer` or the network time shared between client and server.
+///
+/// Closures returning a [`Duration`] are clocks too. Use them with [`sleep_until_clock`],
+/// [`timeout_at_clock`] and [`interval_clock`].
+pub trait Clock {
+    /// Time elapsed since the epoch of the clock.
+    fn now(&self) -> Duration;
+}
+
+impl<F: Fn() -> Duration> Clock for F {
+    fn now(&self) -> Duration {
+        self()
+    }
+}
+
+/// Stops execution until the clock reaches `time` (doesn't block CitizenFX).
+///
+/// The clock is checked again when the timer fires, so the future doesn't complete early
+/// if the clock runs slower than the resource's one or has been adjusted.
+///
+/// # Example
+/// ```rust,ignore
+/// // every client starts the race at the same moment
+/// let game_timer = || Duration::from_millis(get_network_time() as u64);
+/// cfx::runtime::sleep_until_clock(game_timer, race.start_time).await;
+/// ```
+pub fn sleep_until_clock<C: Clock>(clock: C, time: Duration) -> ClockSleep<C> {
+    ClockSleep {
+        clock,
+        time,
+        sleep: None,
+    }
+}
+
+/// A future returned by [`sleep_until_clock`].
+#[derive(Debug)]
+pub struct ClockSleep<C> {
+    clock: C,
+    time: Duration,
+    sleep: Option<Sleep>,
+}
+
+impl<C: Clock> Future for ClockSleep<C> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        poll_clock(&this.clock, this.time, &mut this.sleep, cx).map(|_| ())
+    }
+}
+
+/// Waits until the clock reaches `time` and returns the clock's time at that moment.
+fn poll_clock<C: Clock>(
+    clock: &C,
+    time: Duration,
+    sleep: &mut Option<Sleep>,
+    cx: &mut Context<'_>,
+) -> Poll<Duration> {
+    loop {
+        if let Some(timer) = sleep {
+            futures::ready!(timer.poll_unpin(cx));
+        }
+
+        let now = clock.now();
+
+        if now >= time {
+            *sleep = None;
+            return Poll::Ready(now);
+        }
+
+        *sleep = Some(sleep_for(time - now));
+    }
+}
+
+impl<C: Clock> Unpin for ClockSleep<C> {}
+
+/// Returns the number of timers that haven't completed yet.
+pub fn pending_timers() -> usize {
+    TIMERS.with(|timers| timers.borrow().len())
+}
+
+/// An error returned by [`timeout`] when the deadline has been reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed(());
+
+/// Requires a future to complete before the duration has elapsed.
+///
+/// If the future doesn't complete in time it is dropped and [`Elapsed`] is returned.
+///
+/// # Example
+/// ```rust,ignore
+/// let events = cfx::events::subscribe::<Pong>("server_pong", EventScope::Network);
+/// futures::pin_mut!(events);
+///
+/// match cfx::runtime::timeout(Duration::from_secs(5), events.next()).await {
+///     Ok(Some(pong)) => cfx::log(format!("got a pong: {:?}", pong.payload())),
+///     Ok(None) => (),
+///     Err(_) => cfx::log("server didn't answer in 5 seconds"),
+/// }
+/// ```
+pub async fn timeout<Fut: Future>(duration: Duration, future: Fut) -> Result<Fut::Output, Elapsed> {
+    with_deadline(sleep_for(duration), future).await
+}
+
+/// Requires a future to complete before the clock reaches `time`. See [`timeout`].
+///
+/// # Example
+/// ```rust,ignore
+/// let game_timer = || Duration::from_millis(get_game_timer() as u64);
+///
+/// if cfx::runtime::timeout_at_clock(game_timer, round.end_time, capture_flag()).await.is_err() {
+///     cfx::log("the round is over");
+/// }
+/// ```
+pub async fn timeout_at_clock<C: Clock, Fut: Future>(
+    clock: C,
+    time: Duration,
+    future: Fut,
+) -> Result<Fut::Output, Elapsed> {
+    with_deadline(sleep_until_clock(clock, time), future).await
+}
+
+async fn with_deadline<Fut: Future>(
+    sleep: impl Future<Output = ()>,
+    future: Fut,
+) -> Result<Fut::Output, Elapsed> {
+    futures::pin_mut!(future);
+    futures::pin_mut!(sleep);
+
+    match futures::future::select(future, sleep).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(Elapsed(())),
+    }
+}
+
+/// Defines what [`Interval`] does when it has missed ticks
+/// (for example the server was frozen for a while).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissedTickBehavior {
+    /// Yields all missed ticks as fast as possible until it catches up.
+    #[default]
+    Burst,
+    /// Yields once and schedules the next tick `period` after that moment.
+    Delay,
+    /// Yields once and skips missed ticks keeping the original schedule.
+    Skip,
+}
+
+/// A stream that yields at a fixed period. Created by [`interval`].
+///
+/// Every item is the instant the tick was scheduled for.
+#[derive(Debug)]
+pub struct Interval {
+    period: Duration,
+    deadline: Instant,
+    timer: Option<Sleep>,
+    missed_tick_behavior: MissedTickBehavior,
+}
+
+impl Interval {
+    /// Sets the behavior of missed ticks. Default is [`MissedTickBehavior::Burst`].
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = behavior;
+    }
+
+    /// Returns the behavior of missed ticks.
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.missed_tick_behavior
+    }
+
+    /// Returns the period of the interval.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Resets the interval so the next tick happens `period` after now.
+    pub fn reset(&mut self) {
+        self.deadline = Instant::now() + self.period;
+        self.timer = None;
+    }
+
+    /// Waits for the next tick.
+    pub async fn tick(&mut self) -> Instant {
+        self.next().await.unwrap()
+    }
+}
+
+impl MissedTickBehavior {
+    /// Schedules the tick after `tick` that has been yielded at `now`.
+    fn next_deadline<T>(self, period: Duration, tick: T, now: T) -> T
+    where
+        T: Copy + PartialOrd + Add<Duration, Output = T> + Sub<Output = Duration>,
+    {
+        match self {
+            MissedTickBehavior::Burst => tick + period,
+            MissedTickBehavior::Delay => now + period,
+            MissedTickBehavior::Skip => {
+                let next = tick + period;
+
+                if next > now {
+                    return next;
+                }
+
+                let behind = (now - next).as_nanos();
+                let missed = (behind / period.as_nanos() + 1) as u32;
+
+                next + period * missed
+            }
+        }
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let now = Instant::now();
+
+        if self.deadline > now {
+            let deadline = self.deadline;
+            let timer = self.timer.get_or_insert_with(|| sleep_until(deadline));
+
+            if timer.poll_unpin(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+
+        let tick = self.deadline;
+
+        self.deadline = self
+            .missed_tick_behavior
+            .next_deadline(self.period, tick, now);
+        self.timer = None;
+
+        Poll::Ready(Some(tick))
+    }
+}
+
+/// Creates a stream that yields every `period`. The first tick completes immediately.
+///
+/// Unlike a `loop` with [`sleep_for`] it doesn't drift: ticks are scheduled
+/// from the previous deadline instead of the moment the body has finished.
+///
+/// # Panics
+/// Panics if `period` is zero.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut interval = cfx::runtime::interval(Duration::from_secs(5));
+///
+/// while let Some(_) = interval.next().await {
+///     cfx::log("5 seconds have passed");
+/// }
+/// ```
+pub fn interval(period: Duration) -> Interval {
+    assert!(period > Duration::from_secs(0), "`period` must be non-zero");
+
+    Interval {
+        period,
+        deadline: Instant::now(),
+        timer: None,
+        missed_tick_behavior: MissedTickBehavior::default(),
+    }
+}
+
+/// A stream that yields every `period` of a [`Clock`]. Created by [`interval_clock`].
+///
+/// Every item is the clock's time the tick was scheduled for.
+#[derive(Debug)]
+pub struct ClockInterval<C> {
+    clock: C,
+    period: Duration,
+    deadline: Duration,
+    timer: Option<Sleep>,
+    missed_tick_behavior: MissedTickBehavior,
+}
+
+impl<C: Clock> ClockInterval<C> {
+    /// Sets the behavior of missed ticks. Default is [`MissedTickBehavior::Burst`].
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = behavior;
+    }
+
+    /// Returns the behavior of missed ticks.
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.missed_tick_behavior
+    }
+
+    /// Returns the period of the interval.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Resets the interval so the next tick happens `period` after the clock's now.
+    pub fn reset(&mut self) {
+        self.deadline = self.clock.now() + self.period;
+        self.timer = None;
+    }
+
+    /// Waits for the next tick.
+    pub async fn tick(&mut self) -> Duration {
+        self.next().await.unwrap()
+    }
+}
+
+impl<C: Clock> Stream for ClockInterval<C> {
+    type Item = Duration;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let now = futures::ready!(poll_clock(&this.clock, this.deadline, &mut this.timer, cx));
+        let tick = this.deadline;
+
+        this.deadline = this
+            .missed_tick_behavior
+            .next_deadline(this.period, tick, now);
+
+        Poll::Ready(Some(tick))
+    }
+}
+
+impl<C: Clock> Unpin for ClockInterval<C> {}
+
+/// Creates a stream that yields every `period` of the clock. The first tick completes immediately.
+///
+/// Like [`sleep_until_clock`] it checks the clock when a timer fires, so ticks follow the clock
+/// even if it runs slower than the resource's one.
+///
+/// # Panics
+/// Panics if `period` is zero.
+///
+/// # Example
+/// ```rust,ignore
+/// // all clients refresh the scoreboard at the same moments
+/// let network_time = || Duration::from_millis(get_network_time() as u64);
+/// let mut interval = cfx::runtime::interval_clock(network_time, Duration::from_secs(1));
+///
+/// while let Some(_) = interval.next().await {
+///     refresh_scoreboard();
+/// }
+/// ```
+pub fn interval_clock<C: Clock>(clock: C, period: Duration) -> ClockInterval<C> {
+    assert!(period > Duration::from_secs(0), "`period` must be non-zero");
+
+    ClockInterval {
+        deadline: clock.now(),
+        clock,
+        period,
+        timer: None,
+        missed_tick_behavior: MissedTickBehavior::default(),
+    }
+}
+
+fn current_tick() -> u64 {
+    TICK.with(|tick| tick.get())
+}
+
+fn poll_tick(target: u64, cx: &mut Context<'_>) -> Poll<u64> {
+    let tick = current_tick();
+
+    if tick >= target {
+        return Poll::Ready(tick);
+    }
+
+    wake_at_next_tick(cx.waker());
+
+    Poll::Pending
+}
+
+/// A future that completes after a number of ticks. Created by [`next_tick`] and [`sleep_ticks`].
+#[derive(Debug)]
+pub struct SleepTicks {
+    target: u64,
+}
+
+impl Future for SleepTicks {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        poll_tick(self.target, cx).map(|_| ())
+    }
+}
+
+/// Waits for the next `__cfx_on_tick` (the next server or client frame).
+///
+/// # Example
+/// ```rust,ignore
+/// while get_sequence_progress(ped) != -1 {
+///     cfx::runtime::next_tick().await;
+/// }
+/// ```
+pub fn next_tick() -> SleepTicks {
+    sleep_ticks(1)
+}
+
+/// Waits for `ticks` frames. Zero ticks completes immediately.
+pub fn sleep_ticks(ticks: u64) -> SleepTicks {
+    SleepTicks {
+        target: current_tick() + ticks,
+    }
+}
+
+/// A future that yields once to the executor. Created by [`yield_now`].
+#[derive(Debug)]
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+
+        Poll::Pending
+    }
+}
+
+/// Yields execution back to the executor so other ready tasks can run.
+///
+/// The task is polled again during the same tick.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+/// A stream that yields once per tick. Created by [`ticks`].
+#[derive(Debug)]
+pub struct Ticks {
+    last: u64,
+}
+
+impl Stream for Ticks {
+    type Item = u64;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let tick = futures::ready!(poll_tick(self.last + 1, cx));
+        self.last = tick;
+
+        Poll::Ready(Some(tick))
+    }
+}
+
+/// Creates a stream that yields the number of the current tick once per `__cfx_on_tick`.
+///
+/// If the task was busy for several ticks only the latest one is yielded.
+///
+/// # Example
+/// ```rust,ignore
+/// let ticks = cfx::runtime::ticks();
+/// futures::pin_mut!(ticks);
+///
+/// while let Some(_) = ticks.next().await {
+///     draw_marker();
+/// }
+/// ```
+pub fn ticks() -> Ticks {
+    Ticks {
+        last: current_tick(),
+    }
+}
+
+/// Returns a future that completes when the resource is stopping.
+///
+/// Tasks waiting for it are polled once more before all tasks are dropped,
+/// so it is the place to flush state. Anything after the next `.await` won't run.
+///
+/// # Example
+/// ```rust,ignore
+/// let _ = cfx::runtime::spawn(async {
+///     cfx::runtime::shutdown_signal().await;
+///     save_players();
+/// });
+/// ```
+pub fn shutdown_signal() -> impl Future<Output = ()> {
+    crate::wasm_impl::runtime::watch_resource_stop();
+
     let (tx, rx) = oneshot::channel();
 
-    TIMERS.with(|timers| {
-        let mut timers = timers.borrow_mut();
-        let entry = timers.entry(instant).or_insert_with(Vec::new);
-        entry.push(tx);
-    });
+    if STOPPING.with(|stopping| stopping.get()) {
+        let _ = tx.send(());
+    } else {
+        STOP_SIGNALS.with(|signals| signals.borrow_mut().push(tx));
+    }
 
     rx.unwrap_or_else(|_| ())
 }
+
+/// Registers a callback that is called when the resource is stopping.
+///
+/// Callbacks are called in order of registration after [`shutdown_signal`] has fired
+/// and before pending tasks, timers, event subscriptions and ref functions are dropped.
+///
+/// # Example
+/// ```rust,ignore
+/// let vehicle = create_vehicle(model, x, y, z, heading, true, false);
+///
+/// cfx::runtime::on_stop(move || {
+///     delete_entity(vehicle);
+/// });
+/// ```
+pub fn on_stop<F: FnOnce() + 'static>(callback: F) {
+    crate::wasm_impl::runtime::watch_resource_stop();
+
+    STOP_CALLBACKS.with(|callbacks| callbacks.borrow_mut().push(Box::new(callback)));
+}
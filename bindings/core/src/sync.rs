@@ -0,0 +1,35 @@
+//! Async primitives for tasks running on the resource executor.
+//!
+//! Everything here is single-threaded (`!Send` and `!Sync`) and doesn't allocate
+//! beyond the shared state and the queue of waiting tasks. A wakeup from one task
+//! is handled in the same executor pass, so the woken task runs on the same tick.
+//!
+//! Share a primitive between tasks with `Rc`:
+//! ```rust,ignore
+//! use cfx::sync::Mutex;
+//!
+//! let players = Rc::new(Mutex::new(HashMap::new()));
+//!
+//! let _ = cfx::runtime::spawn({
+//!     let players = players.clone();
+//!
+//!     async move {
+//!         let mut players = players.lock().await;
+//!         players.insert(source, load_player(source).await);
+//!     }
+//! });
+//! ```
+pub mod broadcast;
+pub mod mpsc;
+pub mod watch;
+
+mod mutex;
+mod notify;
+mod rwlock;
+mod semaphore;
+mod wait_list;
+
+pub use mutex::{Mutex, MutexGuard};
+pub use notify::{Notified, Notify};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Acquire, Semaphore, SemaphorePermit};
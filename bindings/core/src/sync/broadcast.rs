@@ -0,0 +1,277 @@
+//! A bounded multi-producer, multi-consumer channel where every receiver gets every value.
+//!
+//! The channel keeps the last `capacity` values. A receiver that falls further behind
+//! gets [`RecvError::Lagged`] and continues from the oldest value that is still kept.
+//!
+//! # Example
+//! ```rust,ignore
+//! let (tx, _) = cfx::sync::broadcast::channel(16);
+//!
+//! for _ in 0..4 {
+//!     let mut rx = tx.subscribe();
+//!
+//!     let _ = cfx::runtime::spawn(async move {
+//!         while let Ok(kill) = rx.recv().await {
+//!             cfx::log(format!("{} killed {}", kill.killer, kill.victim));
+//!         }
+//!     });
+//! }
+//!
+//! let _ = tx.send(Kill { killer, victim });
+//! ```
+use super::wait_list::WaitList;
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+/// There are no receivers. Contains the value that wasn't sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// An error returned by [`Receiver::recv`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// All senders have been dropped and there are no values left.
+    Closed,
+    /// The receiver has missed this many values.
+    Lagged(u64),
+}
+
+/// An error returned by [`Receiver::try_recv`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// There are no values right now.
+    Empty,
+    /// All senders have been dropped and there are no values left.
+    Closed,
+    /// The receiver has missed this many values.
+    Lagged(u64),
+}
+
+struct Shared<T> {
+    buffer: RefCell<VecDeque<T>>,
+    /// Position of the first value in `buffer`.
+    head: Cell<u64>,
+    capacity: usize,
+    senders: Cell<usize>,
+    receivers: Cell<usize>,
+    waiters: RefCell<WaitList>,
+}
+
+impl<T> Shared<T> {
+    fn tail(&self) -> u64 {
+        self.head.get() + self.buffer.borrow().len() as u64
+    }
+}
+
+/// Creates a broadcast channel that keeps up to `capacity` values.
+///
+/// # Panics
+/// Panics if `capacity` is zero.
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "`capacity` must be non-zero");
+
+    let shared = Rc::new(Shared {
+        buffer: RefCell::new(VecDeque::with_capacity(capacity)),
+        head: Cell::new(0),
+        capacity,
+        senders: Cell::new(1),
+        receivers: Cell::new(1),
+        waiters: RefCell::new(WaitList::new()),
+    });
+
+    let sender = Sender {
+        shared: shared.clone(),
+    };
+
+    let receiver = Receiver { shared, next: 0 };
+
+    (sender, receiver)
+}
+
+/// The sending half of a [`channel`].
+pub struct Sender<T> {
+    shared: Rc<Shared<T>>,
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends a value to all receivers. Returns the number of receivers.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let shared = &self.shared;
+        let receivers = shared.receivers.get();
+
+        if receivers == 0 {
+            return Err(SendError(value));
+        }
+
+        {
+            let mut buffer = shared.buffer.borrow_mut();
+
+            if buffer.len() == shared.capacity {
+                buffer.pop_front();
+                shared.head.set(shared.head.get() + 1);
+            }
+
+            buffer.push_back(value);
+        }
+
+        shared.waiters.borrow_mut().notify_all();
+
+        Ok(receivers)
+    }
+
+    /// Creates a new receiver that gets values sent after this call.
+    pub fn subscribe(&self) -> Receiver<T> {
+        self.shared.receivers.set(self.shared.receivers.get() + 1);
+
+        Receiver {
+            shared: self.shared.clone(),
+            next: self.shared.tail(),
+        }
+    }
+
+    /// The number of receivers.
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receivers.get()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.set(self.shared.senders.get() + 1);
+
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let senders = self.shared.senders.get() - 1;
+        self.shared.senders.set(senders);
+
+        if senders == 0 {
+            self.shared.waiters.borrow_mut().notify_all();
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender")
+            .field("receivers", &self.shared.receivers.get())
+            .finish()
+    }
+}
+
+/// The receiving half of a [`channel`].
+pub struct Receiver<T> {
+    shared: Rc<Shared<T>>,
+    /// Position of the next value to receive.
+    next: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Receives the next value if there is one.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let shared = &self.shared;
+        let head = shared.head.get();
+
+        if self.next < head {
+            let missed = head - self.next;
+            self.next = head;
+
+            return Err(TryRecvError::Lagged(missed));
+        }
+
+        let buffer = shared.buffer.borrow();
+
+        match buffer.get((self.next - head) as usize) {
+            Some(value) => {
+                self.next += 1;
+                Ok(value.clone())
+            }
+
+            None if shared.senders.get() == 0 => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Waits for the next value.
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv {
+            receiver: self,
+            waiter: None,
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.set(self.shared.receivers.get() + 1);
+
+        Receiver {
+            shared: self.shared.clone(),
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receivers.set(self.shared.receivers.get() - 1);
+    }
+}
+
+impl<T> std::fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver")
+            .field("next", &self.next)
+            .finish()
+    }
+}
+
+/// A future returned by [`Receiver::recv`].
+pub struct Recv<'a, T> {
+    receiver: &'a mut Receiver<T>,
+    waiter: Option<u64>,
+}
+
+impl<T: Clone> Future for Recv<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        let result = match this.receiver.try_recv() {
+            Ok(value) => Ok(value),
+            Err(TryRecvError::Closed) => Err(RecvError::Closed),
+            Err(TryRecvError::Lagged(missed)) => Err(RecvError::Lagged(missed)),
+            Err(TryRecvError::Empty) => {
+                this.receiver.shared.waiters.borrow_mut().register(
+                    &mut this.waiter,
+                    cx.waker(),
+                    (),
+                );
+
+                return Poll::Pending;
+            }
+        };
+
+        Poll::Ready(result)
+    }
+}
+
+impl<T> Drop for Recv<'_, T> {
+    fn drop(&mut self) {
+        if let Some(id) = self.waiter {
+            self.receiver.shared.waiters.borrow_mut().remove(id);
+        }
+    }
+}
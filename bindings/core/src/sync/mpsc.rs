@@ -0,0 +1,409 @@
+//! A bounded multi-producer, single-consumer channel.
+//!
+//! Senders wait when the channel is full, so a slow consumer slows down producers
+//! instead of growing the queue without limit.
+//!
+//! # Example
+//! ```rust,ignore
+//! let (tx, mut rx) = cfx::sync::mpsc::channel(64);
+//!
+//! let _ = cfx::runtime::spawn(async move {
+//!     while let Some(entry) = rx.recv().await {
+//!         write_log_entry(entry).await;
+//!     }
+//! });
+//!
+//! let _ = tx.send(entry).await;
+//! ```
+use super::wait_list::WaitList;
+use futures::Stream;
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+/// The receiver has been dropped. Contains the value that wasn't sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// An error returned by [`Sender::try_send`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is full.
+    Full(T),
+    /// The receiver has been dropped.
+    Closed(T),
+}
+
+/// An error returned by [`Receiver::try_recv`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// There are no values right now.
+    Empty,
+    /// All senders have been dropped and there are no values left.
+    Disconnected,
+}
+
+struct Shared<T> {
+    queue: RefCell<VecDeque<T>>,
+    capacity: usize,
+    senders: Cell<usize>,
+    closed: Cell<bool>,
+    receiver: RefCell<Option<Waker>>,
+    waiters: RefCell<WaitList>,
+}
+
+impl<T> Shared<T> {
+    fn wake_receiver(&self) {
+        if let Some(waker) = self.receiver.borrow_mut().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Creates a bounded channel that holds up to `capacity` values.
+///
+/// # Panics
+/// Panics if `capacity` is zero.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "`capacity` must be non-zero");
+
+    let shared = Rc::new(Shared {
+        queue: RefCell::new(VecDeque::with_capacity(capacity)),
+        capacity,
+        senders: Cell::new(1),
+        closed: Cell::new(false),
+        receiver: RefCell::new(None),
+        waiters: RefCell::new(WaitList::new()),
+    });
+
+    let sender = Sender {
+        shared: shared.clone(),
+    };
+
+    (sender, Receiver { shared })
+}
+
+/// The sending half of a [`channel`].
+pub struct Sender<T> {
+    shared: Rc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends a value, waiting for free space if the channel is full.
+    pub fn send(&self, value: T) -> Send<'_, T> {
+        Send {
+            sender: self,
+            value: Some(value),
+            waiter: None,
+        }
+    }
+
+    /// Sends a value if there is free space right now.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.shared.closed.get() {
+            return Err(TrySendError::Closed(value));
+        }
+
+        if self.shared.waiters.borrow().has_waiting() {
+            return Err(TrySendError::Full(value));
+        }
+
+        self.push(value).map_err(TrySendError::Full)
+    }
+
+    /// Checks if the receiver has been dropped.
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.get()
+    }
+
+    /// Pushes a value if there is a free slot that isn't reserved for a notified sender.
+    fn push(&self, value: T) -> Result<(), T> {
+        let shared = &self.shared;
+
+        {
+            let mut queue = shared.queue.borrow_mut();
+            let reserved = shared.waiters.borrow().notified();
+
+            if queue.len() + reserved >= shared.capacity {
+                return Err(value);
+            }
+
+            queue.push_back(value);
+        }
+
+        shared.wake_receiver();
+
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.set(self.shared.senders.get() + 1);
+
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let senders = self.shared.senders.get() - 1;
+        self.shared.senders.set(senders);
+
+        if senders == 0 {
+            self.shared.wake_receiver();
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender")
+            .field("queued", &self.shared.queue.borrow().len())
+            .field("closed", &self.shared.closed.get())
+            .finish()
+    }
+}
+
+/// A future returned by [`Sender::send`].
+pub struct Send<'a, T> {
+    sender: &'a Sender<T>,
+    value: Option<T>,
+    waiter: Option<u64>,
+}
+
+impl<T> Future for Send<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: nothing is pinned structurally, `value` is moved only by value.
+        let this = unsafe { self.get_unchecked_mut() };
+        let shared = &this.sender.shared;
+
+        // `Receiver::pop` has notified us about a free slot, it stays reserved
+        // for us until this poll removes the waiter
+        let notified = match this.waiter {
+            Some(id) if !shared.waiters.borrow_mut().poll(id, cx.waker()) => {
+                return Poll::Pending;
+            }
+
+            Some(_) => {
+                this.waiter = None;
+                true
+            }
+
+            None => false,
+        };
+
+        let value = this.value.take().expect("`Send` polled after completion");
+
+        if shared.closed.get() {
+            return Poll::Ready(Err(SendError(value)));
+        }
+
+        let value = if notified || !shared.waiters.borrow().has_waiting() {
+            match this.sender.push(value) {
+                Ok(()) => return Poll::Ready(Ok(())),
+                Err(value) => value,
+            }
+        } else {
+            value
+        };
+
+        this.value = Some(value);
+        this.waiter = Some(shared.waiters.borrow_mut().push(cx.waker(), ()));
+
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Send<'_, T> {
+    fn drop(&mut self) {
+        if let Some(id) = self.waiter {
+            let waiter = self.sender.shared.waiters.borrow_mut().remove(id);
+
+            // pass the free slot on to the next sender
+            if let Some(waiter) = waiter {
+                if waiter.is_notified() {
+                    self.sender.shared.waiters.borrow_mut().notify_one();
+                }
+            }
+        }
+    }
+}
+
+/// The receiving half of a [`channel`].
+pub struct Receiver<T> {
+    shared: Rc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Receives the next value if there is one.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        match self.pop() {
+            Some(value) => Ok(value),
+            None if self.shared.senders.get() == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Waits for the next value. Returns `None` when all senders have been dropped
+    /// and the channel is empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        futures::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Polls for the next value.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        match self.try_recv() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => {
+                let mut receiver = self.shared.receiver.borrow_mut();
+
+                match *receiver {
+                    Some(ref waker) if waker.will_wake(cx.waker()) => (),
+                    _ => *receiver = Some(cx.waker().clone()),
+                }
+
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Closes the channel. Values that were sent already can still be received.
+    pub fn close(&mut self) {
+        self.shared.closed.set(true);
+        self.shared.waiters.borrow_mut().notify_all();
+    }
+
+    /// Takes a value and reserves the freed slot for the first waiting sender.
+    fn pop(&mut self) -> Option<T> {
+        let value = self.shared.queue.borrow_mut().pop_front()?;
+        self.shared.waiters.borrow_mut().notify_one();
+
+        Some(value)
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_recv(cx)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<T> std::fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver")
+            .field("queued", &self.shared.queue.borrow().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker_ref;
+
+    fn poll<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+        Pin::new(future).poll(&mut Context::from_waker(noop_waker_ref()))
+    }
+
+    #[test]
+    fn notified_sender_keeps_its_slot() {
+        let (tx, mut rx) = channel(1);
+        tx.try_send(1).unwrap();
+
+        let mut second = tx.send(2);
+        assert!(poll(&mut second).is_pending());
+
+        assert_eq!(rx.try_recv(), Ok(1));
+
+        // the free slot belongs to `second` until it polls
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+
+        let mut third = tx.send(4);
+        assert!(poll(&mut third).is_pending());
+
+        assert_eq!(poll(&mut second), Poll::Ready(Ok(())));
+        assert!(poll(&mut third).is_pending());
+
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(poll(&mut third), Poll::Ready(Ok(())));
+        assert_eq!(rx.try_recv(), Ok(4));
+    }
+
+    #[test]
+    fn senders_are_served_in_order() {
+        let (tx, mut rx) = channel(1);
+        tx.try_send(0).unwrap();
+
+        let mut sends = [Some(tx.send(1)), Some(tx.send(2)), Some(tx.send(3))];
+
+        for send in sends.iter_mut().flatten() {
+            assert!(poll(send).is_pending());
+        }
+
+        for expected in 0..=3 {
+            assert_eq!(rx.try_recv(), Ok(expected));
+
+            // the last sender polls first and still can't jump the queue
+            for slot in sends.iter_mut().rev() {
+                if let Some(send) = slot {
+                    if poll(send).is_ready() {
+                        *slot = None;
+                    }
+                }
+            }
+        }
+
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn dropped_sender_passes_its_slot_on() {
+        let (tx, mut rx) = channel(1);
+        tx.try_send(1).unwrap();
+
+        let mut first = tx.send(2);
+        let mut second = tx.send(3);
+        assert!(poll(&mut first).is_pending());
+        assert!(poll(&mut second).is_pending());
+
+        assert_eq!(rx.try_recv(), Ok(1));
+        drop(first);
+
+        assert_eq!(poll(&mut second), Poll::Ready(Ok(())));
+        assert_eq!(rx.try_recv(), Ok(3));
+    }
+
+    #[test]
+    fn closed_channel_rejects_waiting_senders() {
+        let (tx, mut rx) = channel(1);
+        tx.try_send(1).unwrap();
+
+        let mut send = tx.send(2);
+        assert!(poll(&mut send).is_pending());
+
+        rx.close();
+
+        assert_eq!(poll(&mut send), Poll::Ready(Err(SendError(2))));
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+}
@@ -0,0 +1,132 @@
+use super::wait_list::WaitList;
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+struct State {
+    permit: bool,
+    /// `true` for waiters notified by `notify_one` so the notification can be passed on.
+    waiters: WaitList<bool>,
+}
+
+/// Notifies a single task or all waiting tasks about an event. Doesn't carry any data.
+///
+/// # Example
+/// ```rust,ignore
+/// let saved = Rc::new(Notify::new());
+///
+/// // in a task that saves players
+/// save_players().await;
+/// saved.notify_waiters();
+///
+/// // in another task
+/// saved.notified().await;
+/// ```
+pub struct Notify {
+    state: RefCell<State>,
+}
+
+impl Notify {
+    /// Creates a `Notify` without a stored notification.
+    pub fn new() -> Notify {
+        Notify {
+            state: RefCell::new(State {
+                permit: false,
+                waiters: WaitList::new(),
+            }),
+        }
+    }
+
+    /// Wakes the first waiting task. If there is none the next call to [`Notify::notified`]
+    /// completes immediately.
+    pub fn notify_one(&self) {
+        let mut state = self.state.borrow_mut();
+        let State { permit, waiters } = &mut *state;
+
+        match waiters.waiting_mut().next() {
+            Some(waiter) => {
+                waiter.data = true;
+                waiter.notify();
+            }
+
+            None => *permit = true,
+        };
+    }
+
+    /// Wakes all tasks that are waiting right now.
+    pub fn notify_waiters(&self) {
+        self.state.borrow_mut().waiters.notify_all();
+    }
+
+    /// Waits for a notification. The task is registered on the first poll.
+    pub fn notified(&self) -> Notified<'_> {
+        Notified {
+            notify: self,
+            waiter: None,
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Notify::new()
+    }
+}
+
+impl std::fmt::Debug for Notify {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Notify")
+            .field("permit", &self.state.borrow().permit)
+            .finish()
+    }
+}
+
+/// A future returned by [`Notify::notified`].
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Option<u64>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let notify = self.notify;
+        let mut state = notify.state.borrow_mut();
+
+        match self.waiter {
+            Some(id) => {
+                if state.waiters.poll(id, cx.waker()) {
+                    self.waiter = None;
+                    return Poll::Ready(());
+                }
+            }
+
+            None if state.permit => {
+                state.permit = false;
+                return Poll::Ready(());
+            }
+
+            None => self.waiter = Some(state.waiters.push(cx.waker(), false)),
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.waiter {
+            let waiter = self.notify.state.borrow_mut().waiters.remove(id);
+
+            if let Some(waiter) = waiter {
+                if waiter.is_notified() && waiter.data {
+                    self.notify.notify_one();
+                }
+            }
+        }
+    }
+}
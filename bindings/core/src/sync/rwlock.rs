@@ -0,0 +1,132 @@
+use super::semaphore::{Semaphore, SemaphorePermit};
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+};
+
+/// A writer takes all permits, every reader takes one.
+const MAX_READS: usize = u32::MAX as usize >> 3;
+
+/// An async reader-writer lock.
+///
+/// Any number of readers or a single writer can hold the lock. Tasks get it in FIFO order,
+/// so a waiting writer isn't starved by new readers.
+pub struct RwLock<T: ?Sized> {
+    semaphore: Semaphore,
+    value: UnsafeCell<T>,
+}
+
+impl<T> RwLock<T> {
+    /// Creates an unlocked lock.
+    pub fn new(value: T) -> RwLock<T> {
+        RwLock {
+            semaphore: Semaphore::new(MAX_READS),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Consumes the lock and returns the value.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Waits for shared read access.
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        let permit = self.semaphore.acquire().await;
+
+        RwLockReadGuard {
+            lock: self,
+            _permit: permit,
+        }
+    }
+
+    /// Waits for exclusive write access.
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let permit = self.semaphore.acquire_many(MAX_READS).await;
+
+        RwLockWriteGuard {
+            lock: self,
+            _permit: permit,
+        }
+    }
+
+    /// Takes read access if it is available right now.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let permit = self.semaphore.try_acquire()?;
+
+        Some(RwLockReadGuard {
+            lock: self,
+            _permit: permit,
+        })
+    }
+
+    /// Takes write access if it is available right now.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let permit = self.semaphore.try_acquire_many(MAX_READS)?;
+
+        Some(RwLockWriteGuard {
+            lock: self,
+            _permit: permit,
+        })
+    }
+
+    /// Returns a mutable reference to the value. No locking is needed as the lock is borrowed mutably.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+impl<T: ?Sized> std::fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RwLock")
+            .field("readers", &(MAX_READS - self.semaphore.available_permits()))
+            .finish()
+    }
+}
+
+/// Shared access to a [`RwLock`]. Released on drop.
+#[must_use]
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: there is no writer while a read permit is held.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+/// Exclusive access to a [`RwLock`]. Released on drop.
+#[must_use]
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: the guard holds all permits, so there is no other reference.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the guard holds all permits, so there is no other reference.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
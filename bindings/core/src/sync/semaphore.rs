@@ -0,0 +1,253 @@
+use super::wait_list::WaitList;
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+struct State {
+    permits: usize,
+    waiters: WaitList<usize>,
+}
+
+/// A counting semaphore. Permits are handed out in FIFO order.
+///
+/// # Example
+/// ```rust,ignore
+/// // don't query the database more than 4 times at once
+/// let semaphore = Rc::new(Semaphore::new(4));
+///
+/// let _permit = semaphore.acquire().await;
+/// query_database().await;
+/// ```
+pub struct Semaphore {
+    state: RefCell<State>,
+}
+
+impl Semaphore {
+    /// Creates a semaphore with the given number of permits.
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            state: RefCell::new(State {
+                permits,
+                waiters: WaitList::new(),
+            }),
+        }
+    }
+
+    /// The number of permits that can be acquired right now.
+    pub fn available_permits(&self) -> usize {
+        self.state.borrow().permits
+    }
+
+    /// Adds permits and wakes tasks waiting for them.
+    pub fn add_permits(&self, permits: usize) {
+        let mut state = self.state.borrow_mut();
+        state.permits += permits;
+
+        let State { permits, waiters } = &mut *state;
+
+        for waiter in waiters.waiting_mut() {
+            if waiter.data > *permits {
+                break;
+            }
+
+            *permits -= waiter.data;
+            waiter.notify();
+        }
+    }
+
+    /// Acquires a single permit.
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Acquires `permits` permits at once.
+    pub fn acquire_many(&self, permits: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            permits,
+            waiter: None,
+        }
+    }
+
+    /// Acquires a single permit if it is available right now.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// Acquires `permits` permits if they are available right now.
+    pub fn try_acquire_many(&self, permits: usize) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state.borrow_mut();
+
+        if state.waiters.has_waiting() || state.permits < permits {
+            return None;
+        }
+
+        state.permits -= permits;
+
+        Some(SemaphorePermit {
+            semaphore: self,
+            permits,
+        })
+    }
+}
+
+impl std::fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("permits", &self.available_permits())
+            .finish()
+    }
+}
+
+/// A future returned by [`Semaphore::acquire`] and [`Semaphore::acquire_many`].
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+    waiter: Option<u64>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+        let permits = self.permits;
+        let mut state = semaphore.state.borrow_mut();
+
+        let acquired = match self.waiter {
+            // permits were taken by `add_permits` for us
+            Some(id) => state.waiters.poll(id, cx.waker()),
+
+            None if !state.waiters.has_waiting() && state.permits >= permits => {
+                state.permits -= permits;
+                true
+            }
+
+            None => {
+                self.waiter = Some(state.waiters.push(cx.waker(), permits));
+                false
+            }
+        };
+
+        if !acquired {
+            return Poll::Pending;
+        }
+
+        self.waiter = None;
+
+        Poll::Ready(SemaphorePermit { semaphore, permits })
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.waiter {
+            let waiter = self.semaphore.state.borrow_mut().waiters.remove(id);
+
+            match waiter {
+                Some(waiter) if waiter.is_notified() => self.semaphore.add_permits(self.permits),
+                // the next waiter may fit into the available permits now
+                Some(_) => self.semaphore.add_permits(0),
+                None => (),
+            }
+        }
+    }
+}
+
+/// Permits acquired from a [`Semaphore`]. They are returned on drop.
+#[must_use]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl SemaphorePermit<'_> {
+    /// Forgets the permits without returning them to the semaphore.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.permits > 0 {
+            self.semaphore.add_permits(self.permits);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker_ref;
+
+    fn poll<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+        Pin::new(future).poll(&mut Context::from_waker(noop_waker_ref()))
+    }
+
+    #[test]
+    fn released_permits_go_to_the_first_waiter() {
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.try_acquire().unwrap();
+
+        let mut first = semaphore.acquire();
+        let mut second = semaphore.acquire();
+        assert!(poll(&mut first).is_pending());
+        assert!(poll(&mut second).is_pending());
+
+        drop(permit);
+
+        // the permit is handed to `first` and can't be taken by anyone else
+        assert!(semaphore.try_acquire().is_none());
+        assert!(poll(&mut second).is_pending());
+
+        let permit = match poll(&mut first) {
+            Poll::Ready(permit) => permit,
+            Poll::Pending => panic!("first waiter didn't get the permit"),
+        };
+
+        drop(permit);
+        assert!(poll(&mut second).is_ready());
+    }
+
+    #[test]
+    fn large_waiter_blocks_the_queue() {
+        let semaphore = Semaphore::new(2);
+        let permit = semaphore.try_acquire_many(2).unwrap();
+
+        let mut large = semaphore.acquire_many(2);
+        let mut small = semaphore.acquire();
+        assert!(poll(&mut large).is_pending());
+        assert!(poll(&mut small).is_pending());
+
+        semaphore.add_permits(1);
+
+        // one permit isn't enough for `large`, and `small` waits behind it
+        assert!(poll(&mut small).is_pending());
+        assert_eq!(semaphore.available_permits(), 1);
+
+        drop(permit);
+
+        assert!(poll(&mut large).is_ready());
+        assert!(poll(&mut small).is_ready());
+    }
+
+    #[test]
+    fn dropped_waiter_returns_its_permits() {
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.try_acquire().unwrap();
+
+        let mut first = semaphore.acquire();
+        let mut second = semaphore.acquire();
+        assert!(poll(&mut first).is_pending());
+        assert!(poll(&mut second).is_pending());
+
+        drop(permit);
+        drop(first);
+
+        assert!(poll(&mut second).is_ready());
+    }
+}
@@ -0,0 +1,137 @@
+use std::{collections::VecDeque, marker::PhantomData, task::Waker};
+
+/// A task waiting in a [`WaitList`].
+pub(crate) struct Waiter<T> {
+    id: u64,
+    waker: Option<Waker>,
+    notified: bool,
+    pub(crate) data: T,
+}
+
+impl<T> Waiter<T> {
+    pub(crate) fn is_notified(&self) -> bool {
+        self.notified
+    }
+
+    /// Marks the waiter as notified and wakes its task.
+    pub(crate) fn notify(&mut self) {
+        self.notified = true;
+
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A FIFO queue of waiting tasks shared by all primitives in [`crate::sync`].
+///
+/// A notified waiter stays in the list until its future polls or removes it,
+/// so a dropped future can pass the notification on.
+pub(crate) struct WaitList<T = ()> {
+    waiters: VecDeque<Waiter<T>>,
+    next_id: u64,
+    _local: PhantomData<*const ()>,
+}
+
+impl<T> WaitList<T> {
+    pub(crate) fn new() -> Self {
+        WaitList {
+            waiters: VecDeque::new(),
+            next_id: 0,
+            _local: PhantomData,
+        }
+    }
+
+    /// Adds a waiter to the end of the list.
+    pub(crate) fn push(&mut self, waker: &Waker, data: T) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.waiters.push_back(Waiter {
+            id,
+            waker: Some(waker.clone()),
+            notified: false,
+            data,
+        });
+
+        id
+    }
+
+    /// Returns `true` and removes the waiter if it has been notified (or doesn't exist),
+    /// otherwise updates its waker.
+    pub(crate) fn poll(&mut self, id: u64, waker: &Waker) -> bool {
+        let position = match self.position(id) {
+            Some(position) => position,
+            None => return true,
+        };
+
+        let waiter = &mut self.waiters[position];
+
+        if waiter.notified {
+            self.waiters.remove(position);
+            return true;
+        }
+
+        match waiter.waker {
+            Some(ref old) if old.will_wake(waker) => (),
+            _ => waiter.waker = Some(waker.clone()),
+        }
+
+        false
+    }
+
+    /// Registers a waiter kept in `slot`: updates the waker if it is still waiting
+    /// or pushes a new one.
+    pub(crate) fn register(&mut self, slot: &mut Option<u64>, waker: &Waker, data: T) {
+        if let Some(id) = *slot {
+            if !self.poll(id, waker) {
+                return;
+            }
+        }
+
+        *slot = Some(self.push(waker, data));
+    }
+
+    /// Removes the waiter from the list.
+    pub(crate) fn remove(&mut self, id: u64) -> Option<Waiter<T>> {
+        let position = self.position(id)?;
+        self.waiters.remove(position)
+    }
+
+    /// Waiters that haven't been notified yet, in order.
+    pub(crate) fn waiting_mut(&mut self) -> impl Iterator<Item = &mut Waiter<T>> {
+        self.waiters.iter_mut().filter(|waiter| !waiter.notified)
+    }
+
+    pub(crate) fn has_waiting(&self) -> bool {
+        self.waiters.iter().any(|waiter| !waiter.notified)
+    }
+
+    /// The number of notified waiters that haven't polled yet.
+    pub(crate) fn notified(&self) -> usize {
+        self.waiters.iter().filter(|waiter| waiter.notified).count()
+    }
+
+    /// Notifies the first waiting task. Returns `false` if there is no one to notify.
+    pub(crate) fn notify_one(&mut self) -> bool {
+        match self.waiting_mut().next() {
+            Some(waiter) => {
+                waiter.notify();
+                true
+            }
+
+            None => false,
+        }
+    }
+
+    /// Notifies all waiting tasks.
+    pub(crate) fn notify_all(&mut self) {
+        for waiter in self.waiting_mut() {
+            waiter.notify();
+        }
+    }
+
+    fn position(&self, id: u64) -> Option<usize> {
+        self.waiters.iter().position(|waiter| waiter.id == id)
+    }
+}
@@ -0,0 +1,211 @@
+//! A channel that keeps only the latest value. Receivers are notified when it changes.
+//!
+//! # Example
+//! ```rust,ignore
+//! let (tx, mut rx) = cfx::sync::watch::channel(Weather::Clear);
+//!
+//! let _ = cfx::runtime::spawn(async move {
+//!     while rx.changed().await.is_ok() {
+//!         set_weather_type_now(&rx.borrow().to_string());
+//!     }
+//! });
+//!
+//! tx.send(Weather::Rain);
+//! ```
+use super::wait_list::WaitList;
+use std::{
+    cell::{Cell, Ref, RefCell},
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+/// The sender has been dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError(());
+
+struct Shared<T> {
+    value: RefCell<T>,
+    version: Cell<u64>,
+    closed: Cell<bool>,
+    receivers: Cell<usize>,
+    waiters: RefCell<WaitList>,
+}
+
+/// Creates a watch channel with an initial value.
+pub fn channel<T>(init: T) -> (Sender<T>, Receiver<T>) {
+    let shared = Rc::new(Shared {
+        value: RefCell::new(init),
+        version: Cell::new(0),
+        closed: Cell::new(false),
+        receivers: Cell::new(1),
+        waiters: RefCell::new(WaitList::new()),
+    });
+
+    let sender = Sender {
+        shared: shared.clone(),
+    };
+
+    let receiver = Receiver { shared, seen: 0 };
+
+    (sender, receiver)
+}
+
+/// The sending half of a [`channel`].
+pub struct Sender<T> {
+    shared: Rc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Replaces the value and notifies all receivers.
+    pub fn send(&self, value: T) {
+        self.send_modify(|old| *old = value);
+    }
+
+    /// Modifies the value in place and notifies all receivers.
+    pub fn send_modify<F: FnOnce(&mut T)>(&self, modify: F) {
+        modify(&mut self.shared.value.borrow_mut());
+
+        self.shared.version.set(self.shared.version.get() + 1);
+        self.shared.waiters.borrow_mut().notify_all();
+    }
+
+    /// Borrows the current value.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.shared.value.borrow()
+    }
+
+    /// Creates a new receiver that sees the current value as seen.
+    pub fn subscribe(&self) -> Receiver<T> {
+        self.shared.receivers.set(self.shared.receivers.get() + 1);
+
+        Receiver {
+            shared: self.shared.clone(),
+            seen: self.shared.version.get(),
+        }
+    }
+
+    /// The number of receivers.
+    pub fn receiver_count(&self) -> usize {
+        self.shared.receivers.get()
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.closed.set(true);
+        self.shared.waiters.borrow_mut().notify_all();
+    }
+}
+
+impl<T> std::fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender")
+            .field("version", &self.shared.version.get())
+            .finish()
+    }
+}
+
+/// The receiving half of a [`channel`].
+pub struct Receiver<T> {
+    shared: Rc<Shared<T>>,
+    seen: u64,
+}
+
+impl<T> Receiver<T> {
+    /// Borrows the current value without marking it as seen.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.shared.value.borrow()
+    }
+
+    /// Borrows the current value and marks it as seen.
+    pub fn borrow_and_update(&mut self) -> Ref<'_, T> {
+        self.seen = self.shared.version.get();
+        self.shared.value.borrow()
+    }
+
+    /// Checks if there is a value that hasn't been seen yet.
+    pub fn has_changed(&self) -> Result<bool, RecvError> {
+        if self.shared.closed.get() {
+            return Err(RecvError(()));
+        }
+
+        Ok(self.shared.version.get() != self.seen)
+    }
+
+    /// Waits for a value that hasn't been seen yet and marks it as seen.
+    ///
+    /// Returns an error if the sender has been dropped.
+    pub fn changed(&mut self) -> Changed<'_, T> {
+        Changed {
+            receiver: self,
+            waiter: None,
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.set(self.shared.receivers.get() + 1);
+
+        Receiver {
+            shared: self.shared.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receivers.set(self.shared.receivers.get() - 1);
+    }
+}
+
+impl<T> std::fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver")
+            .field("seen", &self.seen)
+            .finish()
+    }
+}
+
+/// A future returned by [`Receiver::changed`].
+pub struct Changed<'a, T> {
+    receiver: &'a mut Receiver<T>,
+    waiter: Option<u64>,
+}
+
+impl<T> Future for Changed<'_, T> {
+    type Output = Result<(), RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let shared = &this.receiver.shared;
+        let version = shared.version.get();
+
+        if version != this.receiver.seen {
+            this.receiver.seen = version;
+            return Poll::Ready(Ok(()));
+        }
+
+        if shared.closed.get() {
+            return Poll::Ready(Err(RecvError(())));
+        }
+
+        shared
+            .waiters
+            .borrow_mut()
+            .register(&mut this.waiter, cx.waker(), ());
+
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Changed<'_, T> {
+    fn drop(&mut self) {
+        if let Some(id) = self.waiter {
+            self.receiver.shared.waiters.borrow_mut().remove(id);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Values scoped to a task.
+//!
+//! A task-local value is set for a future with [`LocalKey::scope`] and can be read from anywhere
+//! while that future is being polled, including from nested helpers and log lines.
+//!
+//! # Example
+//! ```rust,ignore
+//! cfx::task_local! {
+//!     static SOURCE: String;
+//! }
+//!
+//! async fn give_money(amount: u32) {
+//!     let source = SOURCE.get();
+//!     cfx::log(format!("[{}] giving {}", source, amount));
+//! }
+//!
+//! while let Some(event) = events.next().await {
+//!     let source = event.source().to_owned();
+//!     let _ = cfx::runtime::spawn(SOURCE.scope(source, give_money(event.payload().amount)));
+//! }
+//! ```
+use std::{
+    cell::RefCell,
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Declares task-local keys of type [`LocalKey`].
+///
+/// ```rust,ignore
+/// cfx::task_local! {
+///     pub static PLAYER: u32;
+///     static CORRELATION_ID: u64;
+/// }
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty; $($rest:tt)*) => {
+        $(#[$attr])*
+        $vis static $name: $crate::task_local::LocalKey<$ty> = {
+            ::std::thread_local! {
+                static __KEY: ::std::cell::RefCell<::std::option::Option<$ty>> = ::std::cell::RefCell::new(::std::option::Option::None);
+            }
+
+            $crate::task_local::LocalKey { inner: __KEY }
+        };
+
+        $crate::task_local!($($rest)*);
+    };
+}
+
+/// A key for a task-local value. Declared with [`task_local!`](crate::task_local!).
+pub struct LocalKey<T: 'static> {
+    #[doc(hidden)]
+    pub inner: std::thread::LocalKey<RefCell<Option<T>>>,
+}
+
+/// An error returned by [`LocalKey::try_with`] outside of a scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessError(());
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("task-local value is not set")
+    }
+}
+
+impl std::error::Error for AccessError {}
+
+impl<T: 'static> LocalKey<T> {
+    /// Sets the value for the duration of the future.
+    ///
+    /// The value is visible only while the future is being polled,
+    /// tasks spawned from it need their own scope.
+    pub fn scope<F: Future>(&'static self, value: T, future: F) -> TaskLocalFuture<T, F> {
+        TaskLocalFuture {
+            key: self,
+            slot: Some(value),
+            future,
+        }
+    }
+
+    /// Sets the value for the duration of the closure.
+    pub fn sync_scope<R, F: FnOnce() -> R>(&'static self, value: T, func: F) -> R {
+        let mut slot = Some(value);
+        self.enter(&mut slot, func)
+    }
+
+    /// Calls the closure with a reference to the value.
+    ///
+    /// # Panics
+    /// Panics if the value is not set.
+    pub fn with<R, F: FnOnce(&T) -> R>(&'static self, func: F) -> R {
+        self.try_with(func)
+            .expect("cannot access a task-local value outside of its scope")
+    }
+
+    /// Calls the closure with a reference to the value if it is set.
+    pub fn try_with<R, F: FnOnce(&T) -> R>(&'static self, func: F) -> Result<R, AccessError> {
+        self.inner.with(|cell| {
+            let value = cell.borrow();
+            value.as_ref().map(func).ok_or(AccessError(()))
+        })
+    }
+
+    /// Swaps `slot` into the key for the duration of the closure.
+    fn enter<R, F: FnOnce() -> R>(&'static self, slot: &mut Option<T>, func: F) -> R {
+        struct Guard<'a, T: 'static> {
+            key: &'static LocalKey<T>,
+            slot: &'a mut Option<T>,
+        }
+
+        impl<'a, T: 'static> Drop for Guard<'a, T> {
+            fn drop(&mut self) {
+                self.key.inner.with(|cell| {
+                    std::mem::swap(self.slot, &mut *cell.borrow_mut());
+                });
+            }
+        }
+
+        self.inner
+            .with(|cell| std::mem::swap(slot, &mut *cell.borrow_mut()));
+
+        // restores the outer value even if the closure panics
+        let _guard = Guard { key: self, slot };
+
+        func()
+    }
+}
+
+impl<T: Clone + 'static> LocalKey<T> {
+    /// Returns a copy of the value.
+    ///
+    /// # Panics
+    /// Panics if the value is not set.
+    pub fn get(&'static self) -> T {
+        self.with(Clone::clone)
+    }
+}
+
+impl<T: 'static> fmt::Debug for LocalKey<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("LocalKey { .. }")
+    }
+}
+
+/// A future with a task-local value. Created by [`LocalKey::scope`].
+pub struct TaskLocalFuture<T: 'static, F> {
+    key: &'static LocalKey<T>,
+    slot: Option<T>,
+    future: F,
+}
+
+impl<T: 'static, F: Future> Future for TaskLocalFuture<T, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved and `slot` is never pinned
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        this.key.enter(&mut this.slot, || future.poll(cx))
+    }
+}
+
+impl<T: 'static, F> fmt::Debug for TaskLocalFuture<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("TaskLocalFuture { .. }")
+    }
+}
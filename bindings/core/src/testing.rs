@@ -0,0 +1,37 @@
+//! Host imports for native unit tests.
+//!
+//! Nothing is registered on the host: natives and ref calls fail, logs go to stdout.
+use crate::types::{call_result, GuestArg, ReturnValue};
+
+#[no_mangle]
+extern "C" fn script_log(message: *const u8) {
+    let message = unsafe { std::ffi::CStr::from_ptr(message as _) };
+    println!("{}", message.to_string_lossy());
+}
+
+#[no_mangle]
+extern "C" fn invoke(_: u64, _: *const GuestArg, _: usize, _: *const ReturnValue) -> i32 {
+    call_result::NULL_RESULT
+}
+
+#[no_mangle]
+extern "C" fn invoke_ref_func(_: *const i8, _: *const u8, _: usize, _: *mut u8, _: usize) -> i32 {
+    call_result::NULL_RESULT
+}
+
+#[no_mangle]
+extern "C" fn canonicalize_ref(_: u32, _: *mut i8, _: usize) -> i32 {
+    0
+}
+
+#[cfg(feature = "host-clock")]
+#[no_mangle]
+extern "C" fn monotonic_time() -> u64 {
+    use std::time::Instant;
+
+    thread_local! {
+        static START: Instant = Instant::now();
+    }
+
+    START.with(|start| start.elapsed().as_nanos() as u64)
+}
@@ -0,0 +1,108 @@
+//! Time measurement used by the runtime.
+//!
+//! By default [`Instant`] is [`std::time::Instant`] which needs WASI (`wasm32-wasi`).
+//! With the `host-clock` feature it is backed by the `cfx.monotonic_time` import instead,
+//! so scripts can be built for `wasm32-unknown-unknown`. The `native` backend reads it
+//! from [`crate::native::HostVtable::monotonic_time`].
+pub use std::time::Duration;
+
+#[cfg(not(feature = "host-clock"))]
+pub use std::time::Instant;
+
+#[cfg(feature = "host-clock")]
+pub use host::Instant;
+
+#[cfg(feature = "host-clock")]
+mod host {
+    use std::ops::{Add, AddAssign, Sub, SubAssign};
+    use std::time::Duration;
+
+    mod ffi {
+        #[cfg(feature = "native")]
+        pub use crate::native::ffi::monotonic_time;
+
+        #[cfg(not(feature = "native"))]
+        #[link(wasm_import_module = "cfx")]
+        extern "C" {
+            /// Nanoseconds since an arbitrary point in the past. Never goes backwards.
+            pub fn monotonic_time() -> u64;
+        }
+    }
+
+    /// A measurement of the host monotonic clock. Mirrors [`std::time::Instant`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct Instant(Duration);
+
+    impl Instant {
+        /// Returns the current time of the host clock.
+        pub fn now() -> Instant {
+            Instant(Duration::from_nanos(unsafe { ffi::monotonic_time() }))
+        }
+
+        /// The amount of time elapsed from `earlier`, or zero if `earlier` is later than `self`.
+        pub fn duration_since(&self, earlier: Instant) -> Duration {
+            self.saturating_duration_since(earlier)
+        }
+
+        /// The amount of time elapsed from `earlier`, or `None` if `earlier` is later than `self`.
+        pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+            self.0.checked_sub(earlier.0)
+        }
+
+        /// The amount of time elapsed from `earlier`, or zero if `earlier` is later than `self`.
+        pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+            self.checked_duration_since(earlier).unwrap_or_default()
+        }
+
+        /// The amount of time elapsed since this instant was created.
+        pub fn elapsed(&self) -> Duration {
+            Instant::now().duration_since(*self)
+        }
+
+        pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+            self.0.checked_add(duration).map(Instant)
+        }
+
+        pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+            self.0.checked_sub(duration).map(Instant)
+        }
+    }
+
+    impl Add<Duration> for Instant {
+        type Output = Instant;
+
+        fn add(self, other: Duration) -> Instant {
+            self.checked_add(other)
+                .expect("overflow when adding duration to instant")
+        }
+    }
+
+    impl AddAssign<Duration> for Instant {
+        fn add_assign(&mut self, other: Duration) {
+            *self = *self + other;
+        }
+    }
+
+    impl Sub<Duration> for Instant {
+        type Output = Instant;
+
+        fn sub(self, other: Duration) -> Instant {
+            self.checked_sub(other)
+                .expect("overflow when subtracting duration from instant")
+        }
+    }
+
+    impl SubAssign<Duration> for Instant {
+        fn sub_assign(&mut self, other: Duration) {
+            *self = *self - other;
+        }
+    }
+
+    impl Sub<Instant> for Instant {
+        type Output = Duration;
+
+        fn sub(self, other: Instant) -> Duration {
+            self.duration_since(other)
+        }
+    }
+}
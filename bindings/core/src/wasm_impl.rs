@@ -1,4 +1,6 @@
 pub mod events;
 pub mod invoker;
+pub mod memory;
 pub mod ref_funcs;
 pub mod runtime;
+pub mod timers;
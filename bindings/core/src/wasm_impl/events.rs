@@ -1,22 +1,96 @@
 use futures::channel::mpsc::UnboundedSender;
 use rustc_hash::FxHashMap;
-use std::{borrow::Cow, cell::RefCell, ffi::CStr};
+use std::{
+    cell::{Cell, RefCell},
+    ffi::CStr,
+    rc::Rc,
+};
 
-use crate::events::{EventScope, RawEvent, RawEventRef};
+use crate::events::{
+    DecodeError, DispatchMode, EventScope, EventSource, RawEvent, RawEventRef, Subscription,
+};
 
 pub(crate) struct EventSub {
+    pub(crate) id: u64,
     pub(crate) scope: EventScope,
     pub(crate) handler: EventHandler,
+    /// Events sent to a stream but not consumed yet.
+    pub(crate) queued: Rc<Cell<usize>>,
 }
 
+#[derive(Clone)]
 pub(crate) enum EventHandler {
     Future(UnboundedSender<RawEvent>),
-    Function(Box<dyn Fn(RawEventRef) + 'static>),
+    Function(Rc<dyn Fn(RawEventRef) + 'static>),
 }
 
-// TODO: Vec<UnboundedSender<Event>>
+type DecodeErrorHook = Rc<dyn Fn(&DecodeError)>;
+
 thread_local! {
-    pub (crate) static EVENTS: RefCell<FxHashMap<String, EventSub>> = RefCell::new(FxHashMap::default());
+    pub (crate) static EVENTS: RefCell<FxHashMap<String, Vec<EventSub>>> = RefCell::new(FxHashMap::default());
+    static NEXT_SUB_ID: Cell<u64> = Cell::new(0);
+    pub(crate) static DISPATCH_MODE: Cell<DispatchMode> = Cell::new(DispatchMode::Immediate);
+    /// Events received since the last executor pass.
+    pub(crate) static PENDING_EVENTS: Cell<u32> = Cell::new(0);
+    pub(crate) static DECODE_ERROR_HOOK: RefCell<Option<DecodeErrorHook>> = RefCell::new(None);
+}
+
+/// Passes a dropped event to the decode error hook or logs it if there is none.
+pub(crate) fn report_decode_error(err: &DecodeError) {
+    // the hook may replace itself so it isn't borrowed while running
+    let hook = DECODE_ERROR_HOOK.with(|hook| hook.borrow().clone());
+
+    match hook {
+        Some(hook) => {
+            crate::panic::catch(|| "decode error hook".to_owned(), || hook(err));
+        }
+        None => crate::log(err.to_string()),
+    }
+}
+
+/// Adds a subscriber. The resource is registered as a handler of the event on the first one.
+pub(crate) fn add_sub(
+    event_name: &str,
+    scope: EventScope,
+    handler: EventHandler,
+    queued: Rc<Cell<usize>>,
+) -> Subscription {
+    let id = NEXT_SUB_ID.with(|next| next.replace(next.get() + 1));
+
+    let first = EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        let subs = events.entry(event_name.to_owned()).or_default();
+
+        subs.push(EventSub {
+            id,
+            scope,
+            handler,
+            queued,
+        });
+
+        subs.len() == 1
+    });
+
+    if first {
+        let _ = crate::invoker::register_resource_as_event_handler(event_name);
+    }
+
+    Subscription::new(event_name.to_owned(), id)
+}
+
+/// Removes a subscriber. Does nothing if it has been removed already.
+pub(crate) fn remove_sub(event_name: &str, id: u64) {
+    let _ = EVENTS.try_with(|events| {
+        if let Ok(mut events) = events.try_borrow_mut() {
+            if let Some(subs) = events.get_mut(event_name) {
+                subs.retain(|sub| sub.id != id);
+
+                if subs.is_empty() {
+                    events.remove(event_name);
+                }
+            }
+        }
+    });
 }
 
 #[no_mangle]
@@ -30,41 +104,78 @@ pub unsafe extern "C" fn __cfx_on_event(
     let payload = std::slice::from_raw_parts(args, args_length as _);
     let source = CStr::from_ptr(source).to_str().unwrap();
 
-    EVENTS.with(|events| {
-        let events = events.borrow();
-
-        if let Some(sub) = events.get(name) {
-            let source = if source.starts_with("net:") {
-                if sub.scope != EventScope::Network {
-                    return;
-                }
+    // an unknown source format shouldn't cost the event
+    let source = source.parse::<EventSource>().unwrap_or_else(|err| {
+        crate::log(format!("event {:?}: {}, treating it as local", name, err));
+        EventSource::Local
+    });
 
-                Cow::from(source.strip_prefix("net:").unwrap())
-            } else if
-            /* is_duplicity_version && */
-            source.starts_with("internal-net:") {
-                Cow::from(source.strip_prefix("internal-net:").unwrap())
-            } else {
-                Cow::from("")
-            };
+    // handlers may subscribe or unsubscribe, so nothing is borrowed while they run
+    let handlers = EVENTS.with(|events| {
+        events
+            .borrow()
+            .get(name)
+            .map(|subs| {
+                subs.iter()
+                    .filter(|sub| !source.is_net() || sub.scope == EventScope::Network)
+                    .map(|sub| (sub.handler.clone(), sub.queued.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    });
 
-            let event = RawEventRef { source, payload };
+    for (handler, queued) in handlers {
+        let event = RawEventRef {
+            source: source.clone(),
+            payload,
+        };
 
-            match sub.handler {
-                EventHandler::Function(ref func) => {
-                    func(event);
-                }
+        match handler {
+            EventHandler::Function(func) => {
+                crate::panic::catch(|| format!("event handler {:?}", name), || func(event));
+            }
 
-                EventHandler::Future(ref sender) => {
-                    let _ = sender.unbounded_send(event.to_raw_event());
+            EventHandler::Future(sender) => {
+                if sender.unbounded_send(event.to_raw_event()).is_ok() {
+                    queued.set(queued.get() + 1);
                 }
             }
         }
-    });
+    }
 
-    crate::runtime::LOCAL_POOL.with(|lp| {
-        if let Ok(mut lp) = lp.try_borrow_mut() {
-            lp.run_until_stalled();
-        }
-    });
+    dispatch();
+
+    if name == "onResourceStop" {
+        crate::wasm_impl::runtime::on_resource_stop(payload);
+    }
+}
+
+/// Runs the executor according to [`DispatchMode`].
+fn dispatch() {
+    let pending = PENDING_EVENTS.with(|pending| pending.get() + 1);
+
+    let run = match DISPATCH_MODE.with(|mode| mode.get()) {
+        DispatchMode::Immediate => true,
+        DispatchMode::Coalesced { max_batch } => pending >= max_batch,
+    };
+
+    if run {
+        crate::wasm_impl::runtime::run_executor();
+    } else {
+        PENDING_EVENTS.with(|events| events.set(pending));
+        crate::wasm_impl::runtime::mark_has_work();
+    }
+}
+
+/// Queue depth of every event subscription.
+pub(crate) fn queue_depths() -> Vec<(String, usize)> {
+    EVENTS.with(|events| {
+        events
+            .borrow()
+            .iter()
+            .flat_map(|(name, subs)| subs.iter().map(move |sub| (name, sub)))
+            .filter(|(_, sub)| matches!(sub.handler, EventHandler::Future(_)))
+            .map(|(name, sub)| (name.clone(), sub.queued.get()))
+            .collect()
+    })
 }
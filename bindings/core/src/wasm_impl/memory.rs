@@ -0,0 +1,156 @@
+extern crate alloc;
+
+use core::alloc::Layout;
+use std::cell::Cell;
+
+thread_local! {
+    static HOST_BUFFERS: Cell<usize> = Cell::new(0);
+    static HOST_BYTES: Cell<usize> = Cell::new(0);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __cfx_alloc(size: u32, align: u32) -> *mut u8 {
+    let layout = Layout::from_size_align_unchecked(size as _, align as _);
+    let ptr = arena::alloc(layout).unwrap_or_else(|| alloc::alloc::alloc(layout));
+
+    if !ptr.is_null() {
+        HOST_BUFFERS.with(|buffers| buffers.set(buffers.get() + 1));
+        HOST_BYTES.with(|bytes| bytes.set(bytes.get() + layout.size()));
+    }
+
+    ptr
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn __cfx_free(ptr: *mut u8, size: u32, align: u32) {
+    let layout = Layout::from_size_align_unchecked(size as _, align as _);
+
+    if !arena::free(ptr) {
+        alloc::alloc::dealloc(ptr, layout);
+    }
+
+    HOST_BUFFERS.with(|buffers| buffers.set(buffers.get().saturating_sub(1)));
+    HOST_BYTES.with(|bytes| bytes.set(bytes.get().saturating_sub(layout.size())));
+}
+
+pub(crate) struct HostStats {
+    pub(crate) buffers: usize,
+    pub(crate) bytes: usize,
+    pub(crate) arena_capacity: usize,
+}
+
+pub(crate) fn host_stats() -> HostStats {
+    HostStats {
+        buffers: HOST_BUFFERS.with(|buffers| buffers.get()),
+        bytes: HOST_BYTES.with(|bytes| bytes.get()),
+        arena_capacity: arena::capacity(),
+    }
+}
+
+/// A bump arena for host buffers. They live only during a single call
+/// (an event or a ref call), so the arena is reset as soon as all of them are freed.
+#[cfg(feature = "host-arena")]
+mod arena {
+    use core::alloc::Layout;
+    use std::cell::RefCell;
+
+    const INITIAL_CAPACITY: usize = 64 * 1024;
+
+    #[derive(Default)]
+    struct Arena {
+        chunk: Box<[u8]>,
+        offset: usize,
+        live: usize,
+    }
+
+    impl Arena {
+        fn contains(&self, ptr: *mut u8) -> bool {
+            let start = self.chunk.as_ptr() as usize;
+            let ptr = ptr as usize;
+
+            ptr >= start && ptr < start + self.chunk.len()
+        }
+
+        fn try_alloc(&mut self, layout: Layout) -> Option<*mut u8> {
+            let start = self.chunk.as_mut_ptr() as usize;
+            let aligned = (start + self.offset + layout.align() - 1) & !(layout.align() - 1);
+            let end = aligned.checked_add(layout.size())?;
+
+            if end > start + self.chunk.len() || layout.size() == 0 {
+                return None;
+            }
+
+            self.offset = end - start;
+            self.live += 1;
+
+            Some(aligned as *mut u8)
+        }
+    }
+
+    thread_local! {
+        static ARENA: RefCell<Arena> = RefCell::new(Arena::default());
+    }
+
+    pub(super) fn alloc(layout: Layout) -> Option<*mut u8> {
+        ARENA.with(|arena| {
+            let mut arena = arena.borrow_mut();
+
+            if let Some(ptr) = arena.try_alloc(layout) {
+                return Some(ptr);
+            }
+
+            // the chunk can be replaced only if nothing points into it
+            if arena.live > 0 {
+                return None;
+            }
+
+            let capacity = (layout.size() + layout.align())
+                .max(arena.chunk.len() * 2)
+                .max(INITIAL_CAPACITY)
+                .next_power_of_two();
+
+            arena.chunk = vec![0; capacity].into_boxed_slice();
+            arena.offset = 0;
+            arena.try_alloc(layout)
+        })
+    }
+
+    pub(super) fn free(ptr: *mut u8) -> bool {
+        ARENA.with(|arena| {
+            let mut arena = arena.borrow_mut();
+
+            if !arena.contains(ptr) {
+                return false;
+            }
+
+            arena.live -= 1;
+
+            if arena.live == 0 {
+                arena.offset = 0;
+            }
+
+            true
+        })
+    }
+
+    pub(super) fn capacity() -> usize {
+        ARENA.with(|arena| arena.borrow().chunk.len())
+    }
+}
+
+#[cfg(not(feature = "host-arena"))]
+mod arena {
+    use core::alloc::Layout;
+
+    pub(super) fn alloc(_: Layout) -> Option<*mut u8> {
+        None
+    }
+
+    pub(super) fn free(_: *mut u8) -> bool {
+        false
+    }
+
+    pub(super) fn capacity() -> usize {
+        0
+    }
+}
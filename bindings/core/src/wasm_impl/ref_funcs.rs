@@ -11,12 +11,21 @@ thread_local! {
 }
 
 mod ffi {
+    #[cfg(feature = "native")]
+    pub use crate::native::ffi::canonicalize_ref;
+
+    #[cfg(not(feature = "native"))]
     #[link(wasm_import_module = "cfx")]
     extern "C" {
         pub fn canonicalize_ref(ref_idx: u32, buffer: *mut i8, buffer_size: usize) -> i32;
     }
 }
 
+/// Calls a ref function.
+///
+/// If the call has failed (the function doesn't exist, arguments couldn't be decoded
+/// or the function has panicked) the result is empty. Every messagepack value takes
+/// at least one byte, so the host can tell a failure from any return value.
 #[doc(hidden)]
 #[no_mangle]
 pub unsafe extern "C" fn __cfx_call_ref(
@@ -26,22 +35,28 @@ pub unsafe extern "C" fn __cfx_call_ref(
 ) -> *const ScrObject {
     let args = std::slice::from_raw_parts(args, args_len);
 
-    HANDLERS.with(|handlers| {
-        let handlers = handlers.borrow();
+    // the function may wake tasks but the executor doesn't run here
+    crate::wasm_impl::runtime::mark_has_work();
 
-        if let Some(handler) = handlers.get(&ref_idx) {
-            BUFFER.with(|buf| {
-                handler.handle(args, buf);
+    let handler = HANDLERS.with(|handlers| handlers.borrow().get(&ref_idx).cloned());
 
-                RETVAL.with(|retval| {
-                    let mut retval = retval.borrow_mut();
-                    let buf = buf.borrow();
+    BUFFER.with(|buf| {
+        let called = match handler {
+            Some(handler) => handler.handle(args, buf),
+            None => false,
+        };
 
-                    retval.data = buf.as_ptr() as _;
-                    retval.length = buf.len() as _;
-                });
-            });
+        if !called {
+            buf.borrow_mut().clear();
         }
+
+        RETVAL.with(|retval| {
+            let mut retval = retval.borrow_mut();
+            let buf = buf.borrow();
+
+            retval.data = buf.as_ptr() as _;
+            retval.length = buf.len() as _;
+        });
     });
 
     RETVAL.with(|scr| scr.as_ptr())
@@ -123,3 +138,29 @@ pub(crate) fn canonicalize_ref(ref_idx: u32) -> String {
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ref_funcs::RefFunction;
+
+    fn call(ref_idx: u32, args: &[u8]) -> Vec<u8> {
+        unsafe {
+            let result = &*__cfx_call_ref(ref_idx, args.as_ptr(), args.len());
+            std::slice::from_raw_parts(result.data as *const u8, result.length as _).to_vec()
+        }
+    }
+
+    #[test]
+    fn failed_calls_return_nothing() {
+        let _double = RefFunction::new(|(value,): (u32,)| value * 2);
+        let idx = REF_IDX.with(|idx| *idx.borrow());
+
+        let result = call(idx, &rmp_serde::to_vec(&(21,)).unwrap());
+        assert_eq!(rmp_serde::from_read_ref::<_, u32>(&result).unwrap(), 42);
+
+        // not a leftover of the previous call
+        assert!(call(idx, &rmp_serde::to_vec(&("21",)).unwrap()).is_empty());
+        assert!(call(idx + 1, &[]).is_empty());
+    }
+}
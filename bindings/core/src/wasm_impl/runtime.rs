@@ -1,55 +1,408 @@
-extern crate alloc;
-
 use futures::{
     channel::oneshot::Sender,
     executor::{LocalPool, LocalSpawner},
 };
 
-use core::alloc::Layout;
-use std::collections::BTreeMap;
-use std::{cell::RefCell, time::Instant};
+use super::timers::TimerQueue;
+use crate::runtime::{Priority, TickBudget};
+use rustc_hash::FxHashMap;
+use std::{
+    cell::{Cell, RefCell},
+    task::Waker,
+    thread::LocalKey,
+};
+
+use crate::time::{Duration, Instant};
 
-#[no_mangle]
-pub unsafe extern "C" fn __cfx_alloc(size: u32, align: u32) -> *mut u8 {
-    let layout = Layout::from_size_align_unchecked(size as _, align as _);
-    alloc::alloc::alloc(layout)
+/// How much of the tick budget has been spent.
+#[derive(Default)]
+pub(crate) struct Budget {
+    pub(crate) limit: TickBudget,
+    pub(crate) overruns: u64,
+    polls: u32,
+    elapsed: Duration,
+    exhausted: bool,
 }
 
-#[no_mangle]
-pub unsafe extern "C" fn __cfx_free(ptr: *mut u8, size: u32, align: u32) {
-    let layout = Layout::from_size_align_unchecked(size as _, align as _);
-    alloc::alloc::dealloc(ptr, layout);
+impl Budget {
+    fn reset(&mut self) {
+        self.polls = 0;
+        self.elapsed = Duration::from_secs(0);
+        self.exhausted = false;
+    }
+
+    /// Takes a poll from the budget. Returns `false` if the budget is exhausted for this tick.
+    ///
+    /// Background tasks may use only a half of the budget. Any budget allows at least one poll,
+    /// so even an empty one doesn't stop the executor.
+    pub(crate) fn acquire(&mut self, priority: Priority) -> bool {
+        let share = match priority {
+            Priority::FrameCritical => 1,
+            Priority::Background => 2,
+        };
+
+        let exhausted = match self.limit {
+            TickBudget::Unlimited => false,
+            TickBudget::Polls(polls) => self.polls >= (polls / share).max(1),
+            TickBudget::Time(time) => self.polls > 0 && self.elapsed >= time / share,
+        };
+
+        if exhausted {
+            if !self.exhausted {
+                self.exhausted = true;
+                self.overruns += 1;
+            }
+
+            return false;
+        }
+
+        self.polls += 1;
+        true
+    }
+
+    pub(crate) fn spend(&mut self, elapsed: Duration) {
+        self.elapsed += elapsed;
+    }
+}
+
+/// What the runtime knows about a spawned task.
+#[derive(Default)]
+pub(crate) struct TaskInfo {
+    pub(crate) name: Option<String>,
+    pub(crate) priority: Priority,
+    pub(crate) polls: u64,
+    pub(crate) busy: Duration,
+    pub(crate) slowest_poll: Duration,
 }
 
 thread_local! {
     pub(crate) static LOCAL_POOL: RefCell<LocalPool> = RefCell::new(LocalPool::new());
     pub(crate) static SPAWNER: RefCell<LocalSpawner> = LOCAL_POOL.with(|lp| RefCell::new(lp.borrow().spawner()));
-    pub(crate) static TIMERS: RefCell<BTreeMap<Instant, Vec<Sender<()>>>> = RefCell::new(BTreeMap::new());
+    pub(crate) static BACKGROUND_POOL: RefCell<LocalPool> = RefCell::new(LocalPool::new());
+    pub(crate) static BACKGROUND_SPAWNER: RefCell<LocalSpawner> = BACKGROUND_POOL.with(|lp| RefCell::new(lp.borrow().spawner()));
+    pub(crate) static TIMERS: RefCell<TimerQueue> = RefCell::new(TimerQueue::default());
+    pub(crate) static TICK: Cell<u64> = Cell::new(0);
+    pub(crate) static TICK_WAKERS: RefCell<Vec<Waker>> = RefCell::new(Vec::new());
+    pub(crate) static BUDGET: RefCell<Budget> = RefCell::new(Budget::default());
+    pub(crate) static TASKS: RefCell<FxHashMap<u64, TaskInfo>> = RefCell::new(FxHashMap::default());
+    pub(crate) static NEXT_TASK_ID: Cell<u64> = Cell::new(0);
+    pub(crate) static TICK_POLLS: Cell<u32> = Cell::new(0);
+    pub(crate) static LAST_TICK_POLLS: Cell<u32> = Cell::new(0);
+    /// Tasks may have been woken or spawned outside of an executor pass.
+    pub(crate) static HAS_WORK: Cell<bool> = Cell::new(true);
+    pub(crate) static STOPPING: Cell<bool> = Cell::new(false);
+    pub(crate) static STOP_SIGNALS: RefCell<Vec<Sender<()>>> = RefCell::new(Vec::new());
+    pub(crate) static STOP_CALLBACKS: RefCell<Vec<Box<dyn FnOnce()>>> = RefCell::new(Vec::new());
+    static WATCHING_STOP: Cell<bool> = Cell::new(false);
+    /// Tasks have to be dropped when the running executor pass ends.
+    static DROP_TASKS_PENDING: Cell<bool> = Cell::new(false);
 }
 
 #[no_mangle]
 pub extern "C" fn __cfx_on_tick() {
+    TICK.with(|tick| tick.set(tick.get() + 1));
+    LAST_TICK_POLLS.with(|last| last.set(TICK_POLLS.with(|polls| polls.replace(0))));
+    BUDGET.with(|budget| budget.borrow_mut().reset());
+    wake_tick_waiters();
     fire_timers();
 
+    run_executor();
+}
+
+/// Runs all ready tasks. Does nothing if the executor is running already.
+///
+/// Frame-critical tasks run first, then background ones. The pools alternate
+/// until a round polls nothing, since tasks of one pool may wake tasks of the other.
+///
+/// If a task has stopped the resource, the pass ends after its pool and the tasks are dropped.
+pub(crate) fn run_executor() {
     LOCAL_POOL.with(|lp| {
-        let mut lp = lp.borrow_mut();
-        lp.run_until_stalled();
+        BACKGROUND_POOL.with(|bg| {
+            if let (Ok(mut lp), Ok(mut bg)) = (lp.try_borrow_mut(), bg.try_borrow_mut()) {
+                HAS_WORK.with(|work| work.set(false));
+                crate::wasm_impl::events::PENDING_EVENTS.with(|events| events.set(0));
+
+                loop {
+                    let polls = TICK_POLLS.with(|polls| polls.get());
+
+                    lp.run_until_stalled();
+
+                    if drop_tasks_pending() {
+                        break;
+                    }
+
+                    bg.run_until_stalled();
+
+                    if drop_tasks_pending() || TICK_POLLS.with(|polls| polls.get()) == polls {
+                        break;
+                    }
+                }
+            }
+        })
     });
+
+    // the pools aren't borrowed anymore (unless this is a nested call)
+    if DROP_TASKS_PENDING.with(|pending| pending.replace(false)) {
+        drop_tasks(&LOCAL_POOL, &SPAWNER);
+        drop_tasks(&BACKGROUND_POOL, &BACKGROUND_SPAWNER);
+    }
+}
+
+fn drop_tasks_pending() -> bool {
+    DROP_TASKS_PENDING.with(|pending| pending.get())
+}
+
+/// [`__cfx_next_wakeup`]: tasks are ready, call `__cfx_on_tick` at the next frame.
+pub const WAKEUP_NOW: i64 = 0;
+
+/// [`__cfx_next_wakeup`]: nothing to do until an event arrives.
+pub const WAKEUP_IDLE: i64 = -1;
+
+/// Tells the host when the resource needs the next `__cfx_on_tick`.
+///
+/// Returns [`WAKEUP_NOW`] if there is work to do, a number of milliseconds until the next timer
+/// or [`WAKEUP_IDLE`] if the resource waits only for events.
+/// Events (and ref calls) must be delivered regardless of the result.
+#[no_mangle]
+pub extern "C" fn __cfx_next_wakeup() -> i64 {
+    let has_work =
+        HAS_WORK.with(|work| work.get()) || TICK_WAKERS.with(|wakers| !wakers.borrow().is_empty());
+
+    if has_work {
+        return WAKEUP_NOW;
+    }
+
+    match TIMERS.with(|timers| timers.borrow_mut().next_deadline()) {
+        Some(deadline) => {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let millis = left.as_micros().saturating_add(999) / 1000;
+
+            millis.min(i64::MAX as u128) as i64
+        }
+
+        None => WAKEUP_IDLE,
+    }
+}
+
+/// Marks that a task may have been woken outside of an executor pass.
+pub(crate) fn mark_has_work() {
+    HAS_WORK.with(|work| work.set(true));
 }
 
 fn fire_timers() {
-    let now = Instant::now();
+    TIMERS.with(|timers| timers.borrow_mut().fire(Instant::now()));
+}
 
-    TIMERS.with(|timers| {
-        let mut timers = timers.borrow_mut();
-        let expiered: Vec<Instant> = timers.range(..=now).map(|(time, _)| *time).collect();
+/// Wakes the task at the next tick.
+pub(crate) fn wake_at_next_tick(waker: &Waker) {
+    TICK_WAKERS.with(|wakers| {
+        let mut wakers = wakers.borrow_mut();
 
-        for key in expiered {
-            if let Some(senders) = timers.remove(&key) {
-                for tx in senders {
-                    let _ = tx.send(());
-                }
-            }
+        if !wakers.iter().any(|other| other.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    });
+}
+
+fn wake_tick_waiters() {
+    let wakers = TICK_WAKERS.with(|wakers| std::mem::take(&mut *wakers.borrow_mut()));
+
+    for waker in wakers {
+        waker.wake();
+    }
+}
+
+/// Called by the host right before the resource is stopped.
+#[no_mangle]
+pub extern "C" fn __cfx_on_stop() {
+    shutdown();
+}
+
+/// Makes the resource listen to `onResourceStop` so [`shutdown`] runs even if the host doesn't call [`__cfx_on_stop`].
+pub(crate) fn watch_resource_stop() {
+    if !WATCHING_STOP.with(|watching| watching.replace(true)) {
+        let _ = crate::invoker::register_resource_as_event_handler("onResourceStop");
+    }
+}
+
+/// Handles `onResourceStop` after it has been dispatched to the subscribers.
+pub(crate) fn on_resource_stop(payload: &[u8]) {
+    let resource = rmp_serde::from_read_ref::<_, (String,)>(payload).map(|(name,)| name);
+    let current = crate::invoker::current_resource_name();
+
+    if let (Ok(resource), Ok(current)) = (resource, current) {
+        if resource == current {
+            shutdown();
         }
+    }
+}
+
+/// Replaces the pool with an empty one and drops its tasks.
+///
+/// If the executor is running (a task has stopped the resource) the tasks are dropped
+/// at the end of the pass by [`run_executor`].
+fn drop_tasks(
+    pool: &'static LocalKey<RefCell<LocalPool>>,
+    spawner: &'static LocalKey<RefCell<LocalSpawner>>,
+) {
+    let old = pool.with(|lp| {
+        lp.try_borrow_mut()
+            .map(|mut lp| std::mem::replace(&mut *lp, LocalPool::new()))
     });
+
+    match old {
+        Ok(old) => {
+            spawner.with(|sp| *sp.borrow_mut() = pool.with(|lp| lp.borrow().spawner()));
+            drop(old);
+        }
+
+        Err(_) => DROP_TASKS_PENDING.with(|pending| pending.set(true)),
+    }
+}
+
+/// Stops the resource runtime. Does nothing if it has been stopped already.
+///
+/// The order is fixed:
+/// 1. [`crate::runtime::shutdown_signal`] futures are resolved and the executor runs once
+///    so tasks can flush their state.
+/// 2. [`crate::runtime::on_stop`] callbacks are called in order of registration.
+/// 3. Pending tasks are dropped. If a task has stopped the resource, they are dropped
+///    at the end of the running executor pass, after the next steps.
+/// 4. Timers and tick waiters are dropped.
+/// 5. Event subscriptions are dropped.
+/// 6. Ref functions are dropped.
+pub(crate) fn shutdown() {
+    if STOPPING.with(|stopping| stopping.replace(true)) {
+        return;
+    }
+
+    // the last pass shouldn't be cut short
+    BUDGET.with(|budget| budget.borrow_mut().limit = TickBudget::Unlimited);
+
+    for signal in STOP_SIGNALS.with(|signals| std::mem::take(&mut *signals.borrow_mut())) {
+        let _ = signal.send(());
+    }
+
+    run_executor();
+
+    for callback in STOP_CALLBACKS.with(|callbacks| std::mem::take(&mut *callbacks.borrow_mut())) {
+        crate::panic::catch(|| String::from("an on_stop callback"), callback);
+    }
+
+    // tasks may own anything else, so they go first
+    drop_tasks(&LOCAL_POOL, &SPAWNER);
+    drop_tasks(&BACKGROUND_POOL, &BACKGROUND_SPAWNER);
+
+    TIMERS.with(|timers| timers.borrow_mut().clear());
+
+    let tick_wakers = TICK_WAKERS.with(|wakers| std::mem::take(&mut *wakers.borrow_mut()));
+    drop(tick_wakers);
+
+    let events =
+        crate::wasm_impl::events::EVENTS.with(|events| std::mem::take(&mut *events.borrow_mut()));
+    drop(events);
+
+    let handlers = crate::wasm_impl::ref_funcs::HANDLERS
+        .with(|handlers| std::mem::take(&mut *handlers.borrow_mut()));
+    drop(handlers);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn background_task_gets_a_poll_of_the_smallest_budget() {
+        crate::runtime::set_tick_budget(TickBudget::Polls(1));
+
+        let done = Rc::new(Cell::new(false));
+        let flag = done.clone();
+        let _ = crate::runtime::spawn_background(async move { flag.set(true) });
+
+        __cfx_on_tick();
+
+        assert!(done.get());
+        assert_eq!(crate::runtime::budget_overruns(), 0);
+    }
+
+    #[test]
+    fn empty_budget_still_polls_once() {
+        let mut budget = Budget::default();
+
+        for limit in [
+            TickBudget::Polls(0),
+            TickBudget::Time(Duration::from_secs(0)),
+        ] {
+            budget.limit = limit;
+            budget.reset();
+
+            assert!(budget.acquire(Priority::FrameCritical));
+            budget.spend(Duration::from_millis(1));
+            assert!(!budget.acquire(Priority::FrameCritical));
+            assert!(!budget.acquire(Priority::Background));
+        }
+
+        assert_eq!(budget.overruns, 2);
+    }
+
+    #[test]
+    fn pools_alternate_until_stalled() {
+        use futures::channel::oneshot;
+
+        let (to_frame, from_background) = oneshot::channel::<()>();
+        let (to_background, from_frame) = oneshot::channel::<()>();
+
+        let done = Rc::new(Cell::new(false));
+        let flag = done.clone();
+
+        // frame -> background -> frame -> background within a single tick
+        let _ = crate::runtime::spawn(async move {
+            let _ = from_background.await;
+            let _ = to_background.send(());
+        });
+
+        let _ = crate::runtime::spawn_background(async move {
+            let _ = to_frame.send(());
+            let _ = from_frame.await;
+            flag.set(true);
+        });
+
+        __cfx_on_tick();
+
+        assert!(done.get());
+        assert_eq!(__cfx_next_wakeup(), WAKEUP_IDLE);
+    }
+
+    #[test]
+    fn tasks_are_dropped_when_a_task_stops_the_resource() {
+        struct Guard(Rc<Cell<u32>>);
+
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let dropped = Rc::new(Cell::new(0));
+
+        let guard = Guard(dropped.clone());
+        let _ = crate::runtime::spawn_background(async move {
+            let _guard = guard;
+            futures::future::pending::<()>().await;
+        });
+
+        let guard = Guard(dropped.clone());
+        let _ = crate::runtime::spawn(async move {
+            let _guard = guard;
+            // like onResourceStop for this resource delivered synchronously
+            shutdown();
+            futures::future::pending::<()>().await;
+        });
+
+        __cfx_on_tick();
+
+        assert_eq!(dropped.get(), 2);
+        assert!(!drop_tasks_pending());
+    }
 }
@@ -0,0 +1,323 @@
+//! A timer queue behind [`crate::runtime::sleep_for`] and friends.
+//!
+//! Timers live in a slab and their deadlines in a binary heap. A cancelled timer
+//! is removed from the slab immediately and its heap entry is skipped (and
+//! eventually compacted away), so firing never allocates.
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    task::{Poll, Waker},
+};
+
+use crate::time::Instant;
+
+/// Compact the heap when it holds more stale entries than this and than live ones.
+const COMPACT_THRESHOLD: usize = 64;
+
+/// A handle to a registered timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TimerKey {
+    index: usize,
+    id: u64,
+}
+
+struct Entry {
+    id: u64,
+    fired: bool,
+    waker: Option<Waker>,
+}
+
+#[derive(Default)]
+pub(crate) struct TimerQueue {
+    slots: Vec<Option<Entry>>,
+    free: Vec<usize>,
+    heap: BinaryHeap<Reverse<(Instant, u64, usize)>>,
+    next_id: u64,
+    live: usize,
+    stale: usize,
+}
+
+impl TimerQueue {
+    /// Registers a new timer.
+    pub(crate) fn insert(&mut self, deadline: Instant) -> TimerKey {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let entry = Entry {
+            id,
+            fired: false,
+            waker: None,
+        };
+
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = Some(entry);
+                index
+            }
+
+            None => {
+                self.slots.push(Some(entry));
+                self.slots.len() - 1
+            }
+        };
+
+        self.heap.push(Reverse((deadline, id, index)));
+        self.live += 1;
+
+        TimerKey { index, id }
+    }
+
+    /// Checks if the timer has fired. A fired timer is removed from the queue.
+    ///
+    /// Timers that don't exist anymore (the queue was cleared) are treated as fired.
+    pub(crate) fn poll(&mut self, key: TimerKey, waker: &Waker) -> Poll<()> {
+        let entry = match self.entry_mut(key) {
+            Some(entry) => entry,
+            None => return Poll::Ready(()),
+        };
+
+        if entry.fired {
+            self.remove(key.index);
+            return Poll::Ready(());
+        }
+
+        match entry.waker {
+            Some(ref old) if old.will_wake(waker) => (),
+            _ => entry.waker = Some(waker.clone()),
+        }
+
+        Poll::Pending
+    }
+
+    /// Removes the timer from the queue.
+    pub(crate) fn cancel(&mut self, key: TimerKey) {
+        let fired = match self.entry_mut(key) {
+            Some(entry) => entry.fired,
+            None => return,
+        };
+
+        self.remove(key.index);
+
+        // a fired timer has left the heap already
+        if !fired {
+            self.stale += 1;
+            self.compact();
+        }
+    }
+
+    /// Fires all timers with a deadline before or at `now`.
+    pub(crate) fn fire(&mut self, now: Instant) {
+        while let Some(&Reverse((deadline, id, index))) = self.heap.peek() {
+            if deadline > now {
+                break;
+            }
+
+            self.heap.pop();
+
+            match self.entry_mut(TimerKey { index, id }) {
+                Some(entry) => {
+                    entry.fired = true;
+
+                    if let Some(waker) = entry.waker.take() {
+                        waker.wake();
+                    }
+                }
+
+                None => self.stale -= 1,
+            }
+        }
+    }
+
+    /// The earliest deadline of a pending timer.
+    pub(crate) fn next_deadline(&mut self) -> Option<Instant> {
+        while let Some(&Reverse((deadline, id, index))) = self.heap.peek() {
+            if self.entry_mut(TimerKey { index, id }).is_some() {
+                return Some(deadline);
+            }
+
+            self.heap.pop();
+            self.stale -= 1;
+        }
+
+        None
+    }
+
+    /// The number of timers that haven't completed yet.
+    pub(crate) fn len(&self) -> usize {
+        self.live
+    }
+
+    /// Drops all timers. Their futures complete on the next poll.
+    pub(crate) fn clear(&mut self) {
+        self.slots.clear();
+        self.free.clear();
+        self.heap.clear();
+        self.live = 0;
+        self.stale = 0;
+    }
+
+    fn entry_mut(&mut self, key: TimerKey) -> Option<&mut Entry> {
+        self.slots
+            .get_mut(key.index)
+            .and_then(|slot| slot.as_mut())
+            .filter(|entry| entry.id == key.id)
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.slots[index] = None;
+        self.free.push(index);
+        self.live -= 1;
+    }
+
+    fn compact(&mut self) {
+        if self.stale < COMPACT_THRESHOLD || self.stale < self.live {
+            return;
+        }
+
+        let slots = &self.slots;
+        let mut heap = std::mem::take(&mut self.heap).into_vec();
+
+        heap.retain(|Reverse((_, id, index))| {
+            matches!(slots.get(*index), Some(Some(entry)) if entry.id == *id)
+        });
+
+        self.heap = BinaryHeap::from(heap);
+        self.stale = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::{noop_waker_ref, waker, ArcWake};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    #[derive(Default)]
+    struct WakeCounter(AtomicUsize);
+
+    impl ArcWake for WakeCounter {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn at(start: Instant, millis: u64) -> Instant {
+        start + Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn fires_due_timers_in_order() {
+        let start = Instant::now();
+        let counter = Arc::new(WakeCounter::default());
+        let wake = waker(counter.clone());
+
+        let mut timers = TimerQueue::default();
+        let late = timers.insert(at(start, 20));
+        let early = timers.insert(at(start, 10));
+
+        assert!(timers.poll(early, &wake).is_pending());
+        assert!(timers.poll(late, &wake).is_pending());
+        assert_eq!(timers.next_deadline(), Some(at(start, 10)));
+
+        timers.fire(at(start, 15));
+
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert!(timers.poll(late, &wake).is_pending());
+        assert!(timers.poll(early, &wake).is_ready());
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers.next_deadline(), Some(at(start, 20)));
+
+        timers.fire(at(start, 20));
+
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+        assert!(timers.poll(late, &wake).is_ready());
+        assert_eq!(timers.len(), 0);
+        assert_eq!(timers.next_deadline(), None);
+    }
+
+    #[test]
+    fn cancelled_timer_never_fires() {
+        let start = Instant::now();
+        let counter = Arc::new(WakeCounter::default());
+        let wake = waker(counter.clone());
+
+        let mut timers = TimerQueue::default();
+        let cancelled = timers.insert(at(start, 10));
+        let pending = timers.insert(at(start, 20));
+
+        assert!(timers.poll(cancelled, &wake).is_pending());
+        timers.cancel(cancelled);
+
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers.next_deadline(), Some(at(start, 20)));
+
+        timers.fire(at(start, 15));
+
+        assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+        assert!(timers.poll(pending, noop_waker_ref()).is_pending());
+    }
+
+    #[test]
+    fn reused_slot_ignores_the_old_heap_entry() {
+        let start = Instant::now();
+        let mut timers = TimerQueue::default();
+
+        let old = timers.insert(at(start, 10));
+        timers.cancel(old);
+
+        // takes the slot of the cancelled timer while its heap entry is still there
+        let new = timers.insert(at(start, 30));
+        assert_eq!(new.index, old.index);
+        assert_ne!(new, old);
+
+        timers.fire(at(start, 20));
+
+        assert!(timers.poll(new, noop_waker_ref()).is_pending());
+        assert_eq!(timers.stale, 0);
+
+        timers.fire(at(start, 30));
+
+        assert!(timers.poll(new, noop_waker_ref()).is_ready());
+    }
+
+    #[test]
+    fn stale_entries_get_compacted() {
+        let start = Instant::now();
+        let mut timers = TimerQueue::default();
+
+        let keep = timers.insert(at(start, 1000));
+        let cancelled = (0..COMPACT_THRESHOLD as u64)
+            .map(|millis| timers.insert(at(start, millis)))
+            .collect::<Vec<_>>();
+
+        for key in cancelled {
+            timers.cancel(key);
+        }
+
+        assert_eq!(timers.stale, 0);
+        assert_eq!(timers.heap.len(), 1);
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers.next_deadline(), Some(at(start, 1000)));
+
+        timers.fire(at(start, 1000));
+        assert!(timers.poll(keep, noop_waker_ref()).is_ready());
+    }
+
+    #[test]
+    fn cleared_timers_complete() {
+        let start = Instant::now();
+        let mut timers = TimerQueue::default();
+
+        let key = timers.insert(at(start, 10));
+        timers.clear();
+
+        assert_eq!(timers.len(), 0);
+        assert!(timers.poll(key, noop_waker_ref()).is_ready());
+    }
+}
@@ -0,0 +1,180 @@
+//! Derive macros for `cfx`. Use them through `cfx::events::CfxEvent`.
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput, Error, Lit, Meta, NestedMeta, Path};
+
+/// Declares an event next to its payload.
+///
+/// ```rust,ignore
+/// #[derive(Serialize, Deserialize, CfxEvent)]
+/// #[cfx(name = "bank:deposit", scope = "network")]
+/// struct Deposit {
+///     amount: u32,
+/// }
+/// ```
+///
+/// Attributes:
+/// * `name` is the name of the event, required,
+/// * `scope` is `"local"` (default) or `"network"`, only network events get
+///   `emit_to_server`, `emit_to_client` and `emit_to_all_clients`,
+/// * `crate` is a path to the `cfx` crate, `::cfx` by default.
+///
+/// Type parameters of a generic payload get `Serialize + DeserializeOwned + 'static` bounds.
+#[proc_macro_derive(CfxEvent, attributes(cfx))]
+pub fn derive_cfx_event(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+struct Attrs {
+    name: String,
+    network: bool,
+    krate: Path,
+}
+
+fn parse_attrs(input: &DeriveInput) -> Result<Attrs, Error> {
+    let mut name = None;
+    let mut network = false;
+    let mut krate = syn::parse_quote!(::cfx);
+
+    for attr in input.attrs.iter().filter(|attr| attr.path.is_ident("cfx")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new_spanned(meta, "expected #[cfx(...)]")),
+        };
+
+        for nested in list.nested {
+            let pair = match nested {
+                NestedMeta::Meta(Meta::NameValue(pair)) => pair,
+                nested => return Err(Error::new_spanned(nested, "expected `key = \"value\"`")),
+            };
+
+            let value = match &pair.lit {
+                Lit::Str(value) => value,
+                lit => return Err(Error::new_spanned(lit, "expected a string")),
+            };
+
+            if pair.path.is_ident("name") {
+                if value.value().is_empty() {
+                    return Err(Error::new_spanned(value, "event name can't be empty"));
+                }
+
+                name = Some(value.value());
+            } else if pair.path.is_ident("scope") {
+                network = match value.value().as_str() {
+                    "local" => false,
+                    "network" => true,
+                    _ => return Err(Error::new_spanned(value, "expected `local` or `network`")),
+                };
+            } else if pair.path.is_ident("crate") {
+                krate = value.parse()?;
+            } else {
+                return Err(Error::new_spanned(
+                    pair.path,
+                    "unknown attribute, expected `name`, `scope` or `crate`",
+                ));
+            }
+        }
+    }
+
+    let name = name.ok_or_else(|| {
+        Error::new(
+            Span::call_site(),
+            "missing the event name: #[cfx(name = \"...\")]",
+        )
+    })?;
+
+    Ok(Attrs {
+        name,
+        network,
+        krate,
+    })
+}
+
+fn expand(mut input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let Attrs {
+        name,
+        network,
+        krate,
+    } = parse_attrs(&input)?;
+
+    let params = input
+        .generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect::<Vec<_>>();
+
+    let where_clause = input.generics.make_where_clause();
+
+    for param in params {
+        where_clause.predicates.push(syn::parse_quote! {
+            #param: #krate::__private::Serialize + #krate::__private::DeserializeOwned + 'static
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let scope = if network {
+        quote!(#krate::events::EventScope::Network)
+    } else {
+        quote!(#krate::events::EventScope::Local)
+    };
+
+    // a local event can't be sent over the network by mistake
+    let network_methods = if network {
+        quote! {
+            /// Emits the event to the server. Client only.
+            pub fn emit_to_server(&self) {
+                #krate::events::emit_to_server(Self::EVENT_NAME, self);
+            }
+
+            /// Emits the event to a client. Server only.
+            pub fn emit_to_client(&self, target: &#krate::events::EventSource) {
+                #krate::events::emit_to_client(Self::EVENT_NAME, target, self);
+            }
+
+            /// Emits the event to every client. Server only.
+            pub fn emit_to_all_clients(&self) {
+                #krate::events::emit_to_all_clients(Self::EVENT_NAME, self);
+            }
+        }
+    } else {
+        quote!()
+    };
+
+    Ok(quote! {
+        impl #impl_generics #ident #ty_generics #where_clause {
+            /// Name of the event.
+            pub const EVENT_NAME: &'static str = #name;
+
+            /// Scope of the event.
+            pub const EVENT_SCOPE: #krate::events::EventScope = #scope;
+
+            /// Emits the event locally.
+            pub fn emit(&self) {
+                #krate::events::emit(Self::EVENT_NAME, self);
+            }
+
+            #network_methods
+
+            /// Subscribes on the event. See `cfx::events::subscribe`.
+            pub fn subscribe() -> impl #krate::__private::Stream<Item = #krate::events::Event<'static, Self>> {
+                #krate::events::subscribe(Self::EVENT_NAME, Self::EVENT_SCOPE)
+            }
+
+            /// Sets a handler of the event. See `cfx::events::set_event_handler_closure`.
+            pub fn set_handler<H>(handler: H) -> #krate::events::Subscription
+            where
+                H: Fn(#krate::events::Event<'_, Self>) + 'static,
+            {
+                #krate::events::set_event_handler_closure(Self::EVENT_NAME, handler, Self::EVENT_SCOPE)
+            }
+        }
+    })
+}
@@ -0,0 +1,13 @@
+use cfx_core::events::CfxEvent;
+
+#[derive(serde::Serialize, serde::Deserialize, CfxEvent)]
+#[cfx(name = "bank:generic", crate = "cfx_core")]
+struct Generic<T> {
+    value: T,
+}
+
+struct NotSerializable;
+
+fn main() {
+    Generic { value: NotSerializable }.emit();
+}
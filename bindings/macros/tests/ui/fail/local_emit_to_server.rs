@@ -0,0 +1,9 @@
+use cfx_core::events::CfxEvent;
+
+#[derive(serde::Serialize, serde::Deserialize, CfxEvent)]
+#[cfx(name = "bank:local", crate = "cfx_core")]
+struct Local;
+
+fn main() {
+    Local.emit_to_server();
+}
@@ -0,0 +1,7 @@
+use cfx_core::events::CfxEvent;
+
+#[derive(serde::Serialize, serde::Deserialize, CfxEvent)]
+#[cfx(scope = "network", crate = "cfx_core")]
+struct Deposit;
+
+fn main() {}
@@ -0,0 +1,7 @@
+use cfx_core::events::CfxEvent;
+
+#[derive(serde::Serialize, serde::Deserialize, CfxEvent)]
+#[cfx(nmae = "bank:deposit", crate = "cfx_core")]
+struct Deposit;
+
+fn main() {}
@@ -0,0 +1,41 @@
+use cfx_core::events::{CfxEvent, EventScope, EventSource};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, CfxEvent)]
+#[cfx(name = "bank:deposit", scope = "network", crate = "cfx_core")]
+struct Deposit {
+    amount: u32,
+}
+
+#[derive(Serialize, Deserialize, CfxEvent)]
+#[cfx(name = "bank:local", crate = "cfx_core")]
+struct Local(u8);
+
+#[derive(Serialize, Deserialize, CfxEvent)]
+#[cfx(name = "bank:generic", scope = "local", crate = "::cfx_core")]
+struct Generic<T> {
+    value: T,
+}
+
+// not called, the host imports aren't linked
+#[allow(dead_code)]
+fn methods(source: &EventSource) {
+    Deposit { amount: 1 }.emit();
+    Deposit { amount: 1 }.emit_to_server();
+    Deposit { amount: 1 }.emit_to_client(source);
+    Deposit { amount: 1 }.emit_to_all_clients();
+    let _ = Deposit::subscribe();
+
+    Local(1).emit();
+    Local::set_handler(|event| drop(event.into_inner())).detach();
+
+    Generic { value: String::new() }.emit();
+    let _ = Generic::<Vec<u32>>::subscribe();
+}
+
+fn main() {
+    assert_eq!(Deposit::EVENT_NAME, "bank:deposit");
+    assert_eq!(Deposit::EVENT_SCOPE, EventScope::Network);
+    assert_eq!(Local::EVENT_SCOPE, EventScope::Local);
+    assert_eq!(Generic::<u8>::EVENT_NAME, "bank:generic");
+}
@@ -1,7 +1,29 @@
+use cfx_core::events::EventSource;
 use serde::Serialize;
 
 pub mod natives;
 
+pub mod clock {
+    use crate::natives::cfx::get_game_timer;
+    use cfx_core::runtime::{sleep_until_clock, Clock, ClockSleep};
+    use std::time::Duration;
+
+    /// `GetGameTimer`: time since the server has started.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct GameTimer;
+
+    impl Clock for GameTimer {
+        fn now(&self) -> Duration {
+            Duration::from_millis(get_game_timer().max(0) as u64)
+        }
+    }
+
+    /// Stops execution until `GetGameTimer` reaches `time`.
+    pub fn sleep_until_game_time(time: Duration) -> ClockSleep<GameTimer> {
+        sleep_until_clock(GameTimer, time)
+    }
+}
+
 pub mod events {
     use cfx_core::events::Event;
     use cfx_core::ref_funcs::ExternRefFunction;
@@ -31,13 +53,23 @@ pub mod events {
     }
 }
 
-pub fn emit_net<T: Serialize>(event_name: &str, source: &str, payload: T) {
-    if let Ok(payload) = rmp_serde::to_vec(&payload) {
-        natives::cfx::trigger_client_event_internal(
-            event_name,
-            source,
-            payload.as_slice(),
-            payload.len() as _,
-        );
-    }
+/// Emits a network event to a client. Reply to the sender of an event with [`Event::source`].
+///
+/// Does nothing if the target isn't [`EventSource::Client`]. Use [`emit_net_all`] to emit to every client.
+///
+/// # Example
+/// ```rust,ignore
+/// while let Some(event) = events.next().await {
+///     cfx::server::emit_net("pong", event.source(), event.payload());
+/// }
+/// ```
+///
+/// [`Event::source`]: cfx_core::events::Event::source
+pub fn emit_net<T: Serialize>(event_name: &str, target: &EventSource, payload: T) {
+    cfx_core::events::emit_to_client(event_name, target, payload);
+}
+
+/// Emits a network event to every client.
+pub fn emit_net_all<T: Serialize>(event_name: &str, payload: T) {
+    cfx_core::events::emit_to_all_clients(event_name, payload);
 }
@@ -147,9 +147,10 @@ pub extern "C" fn _start() {
             Some(())
         };
 
-        loop {
+        let mut interval = cfx::runtime::interval(Duration::from_secs(5));
+
+        while interval.next().await.is_some() {
             wrapper();
-            cfx::runtime::sleep_for(Duration::from_secs(5)).await;
         }
     };
 
@@ -16,11 +16,11 @@ async fn handle_connections() {
             event.source(),
         ));
 
-        let src = event.source();
-        let idents_count = get_num_player_identifiers(src);
+        let src = event.source().to_string();
+        let idents_count = get_num_player_identifiers(src.as_str());
 
         for i in 0..idents_count {
-            let ident = get_player_identifier(src, i);
+            let ident = get_player_identifier(src.as_str(), i);
             cfx::log(format!("ident: {:?}", ident));
         }
 
@@ -100,7 +100,7 @@ fn create_export() {
         vec![0.0]
     });
 
-    cfx::exports::make_export("vecLength", export);
+    cfx::exports::make_export("vecLength", export).detach();
 }
 
 async fn test_exports() {
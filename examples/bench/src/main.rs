@@ -1,4 +1,7 @@
-use cfx::{events::Event, ref_funcs::RefFunction};
+use cfx::{
+    events::{Event, EventSource},
+    ref_funcs::RefFunction,
+};
 use easybench::bench;
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
@@ -98,7 +101,28 @@ fn bench_event_handler() {
     );
 }
 
-async fn event_handle(source: String, event: CustomEvent) -> Result<(), ()> {
+fn bench_coalesced_dispatch() {
+    use cfx::events::{emit, set_dispatch_mode, DispatchMode};
+
+    #[derive(Debug, Serialize)]
+    struct CustomEvent((u32, &'static str));
+
+    set_dispatch_mode(DispatchMode::Coalesced { max_batch: 128 });
+
+    log!(
+        "bench_coalesced_dispatch::wasm_async (long) {}",
+        bench(|| emit("wasmEventHandlerAsync", CustomEvent((512, LONG_STRING))))
+    );
+
+    log!(
+        "bench_coalesced_dispatch::wasm_async (short) {}",
+        bench(|| emit("wasmEventHandlerAsync", CustomEvent((256, SHORT_STRING))))
+    );
+
+    set_dispatch_mode(DispatchMode::Immediate);
+}
+
+async fn event_handle(source: EventSource, event: CustomEvent) -> Result<(), ()> {
     Ok(())
 }
 
@@ -106,7 +130,7 @@ fn main() {
     // startup
     fn create_export() {
         let func = RefFunction::new(|_: Vec<u32>| {});
-        cfx::exports::make_export("exportBench", func);
+        cfx::exports::make_export("exportBench", func).detach();
     }
 
     fn set_event_handler() {
@@ -114,13 +138,15 @@ fn main() {
             "wasmEventHandler",
             cfx::events::handler_fn(event_handle),
             cfx::events::EventScope::Local,
-        );
+        )
+        .detach();
 
         cfx::events::set_event_handler_closure(
             "wasmEventHandlerClosure",
             |_ev: Event<CustomEvent>| {},
             cfx::events::EventScope::Local,
-        );
+        )
+        .detach();
 
         let events = cfx::events::subscribe::<CustomEvent>(
             "wasmEventHandlerAsync",
@@ -145,6 +171,7 @@ fn main() {
     // benchmarks
     bench_exports();
     bench_event_handler();
+    bench_coalesced_dispatch();
     bench_invoking();
 }
 